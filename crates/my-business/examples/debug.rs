@@ -1,23 +1,34 @@
 use my_business::my_application::WorkerApplicationOne as BusinessApplication;
+use my_business::my_application::WorkerConfig;
 use windows_service_rs_core::application::{SimpleApplication};
 use windows_service_rs_core::error::{ServiceResult, ServiceError};
-use std::sync::{Arc};
+use windows_service_rs_core::pause_signal::PauseSignal;
+use windows_service_rs_core::run_context::RunContext;
+use windows_service_rs_core::shutdown_token::ShutdownToken;
 use std::io::{stdin};
-use std::sync::atomic::{AtomicBool, Ordering};
-use windows_service_rs_core::win_dbg_logger;
+
+#[cfg(windows)]
+macro_rules! init_logger {
+    () => { windows_service_rs_core::win_dbg_logger::init(); }
+}
+
+#[cfg(not(windows))]
+macro_rules! init_logger {
+    () => { simple_logger::init().unwrap_or_else(|_|{}) }
+}
 
 fn main() -> ServiceResult<()> {
-    win_dbg_logger::init();
-    simulate(|| { Box::new(BusinessApplication {}) })
+    init_logger!();
+    simulate(|| { Box::new(BusinessApplication { config: WorkerConfig::default() }) })
 }
 
 fn simulate(application_factory:fn() -> Box<dyn SimpleApplication>) -> ServiceResult<()> {
-    let exit_signal = Arc::new(AtomicBool::new(false));
+    let shutdown_token = ShutdownToken::new();
 
-    let exit_signal_for_thread = exit_signal.clone();
+    let context = RunContext::from_shutdown_token(shutdown_token.clone(), PauseSignal::new());
     let handle = std::thread::spawn(move || -> ServiceResult<()> {
         let application = application_factory();
-        application.run(exit_signal_for_thread)?;
+        application.run_with_context(&context)?;
         Ok(())
     });
 
@@ -27,7 +38,7 @@ fn simulate(application_factory:fn() -> Box<dyn SimpleApplication>) -> ServiceRe
     stdin().read_line(&mut user_input).map_err(|e| { ServiceError::with(e, "IO error. ") })?;
 
     println!("Application is about to exit!");
-    exit_signal.store(true, Ordering::SeqCst);
+    shutdown_token.cancel();
 
     return match handle.join() {
         Ok(_) => { Ok(()) }
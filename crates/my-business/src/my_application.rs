@@ -1,39 +1,188 @@
-use windows_service_rs_core::error::{ServiceError, ServiceResult};
-use std::sync::{Arc};
-use std::thread;
+use windows_service_rs_core::async_application::{AsyncApplication, BoxFuture};
+use windows_service_rs_core::command::UserCommand;
+use windows_service_rs_core::configuration::ConfigurationChange;
+use windows_service_rs_core::error::{ErrorKind, ServiceError, ServiceResult};
+use windows_service_rs_core::interval_worker::{IntervalWorker, MissedTicks};
+use windows_service_rs_core::message_bus::{Overflow, Subscriber};
+use windows_service_rs_core::run_context::RunContext;
+use windows_service_rs_core::scheduled_application::JobRun;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use std::sync::atomic::{AtomicBool, Ordering};
 
-pub struct WorkerApplicationOne {}
+// Worker 1 publishes the number of each iteration on this topic, and Worker 3 receives them.
+pub const WORK_ITEMS_TOPIC: &str = "work-items";
+
+// The configuration section of a worker, E.g. `[worker-one]` in the configuration file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    // The pause between two iterations of the work loop.
+    pub interval_secs: u64,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig { interval_secs: 2 }
+    }
+}
+
+impl WorkerConfig {
+    fn validate(&self) -> ServiceResult<()> {
+        if self.interval_secs == 0 {
+            return Err(ServiceError::of_kind(ErrorKind::Config, "interval_secs must be at least 1. "));
+        }
+        Ok(())
+    }
+}
+
+pub struct WorkerApplicationOne {
+    pub config: WorkerConfig,
+}
 
 impl windows_service_rs_core::application::SimpleApplication for WorkerApplicationOne {
     fn handle_error(&self, error: &ServiceError) {
         log::error!("Application error: {:?}", error);
     }
 
-    fn run(&self, exit_signal: Arc<AtomicBool>) -> ServiceResult<()> {
-        do_some_work(String::from("Worker 1"), exit_signal);
+    // The service reports Running only after this returns, and it fails to start if this
+    // returns an error.
+    fn initialize(&self, _context: &RunContext) -> ServiceResult<()> {
+        self.config.validate()?;
+        log::info!("Thread is initialized ({:?}) - Worker 1", self.config);
         Ok(())
     }
+
+    fn run_with_context(&self, context: &RunContext) -> ServiceResult<()> {
+        let work_items = context.message_bus().publisher::<u64>(WORK_ITEMS_TOPIC)?;
+        let worker = worker("Worker 1", self.config.interval_secs);
+        do_some_work(&worker, |iteration| { work_items.publish(iteration) }, context)
+    }
+
+    fn supports_pause(&self) -> bool { true }
+}
+
+// The control code of the command which logs the interval of Worker 2, E.g.
+// `sc control sample-rust-service 130`.
+pub const REPORT_INTERVAL_COMMAND: u8 = 130;
+// The control codes which add or remove a replica of Worker 2, E.g.
+// `sc control sample-rust-service 140`.
+pub const SCALE_UP_COMMAND: u8 = 140;
+pub const SCALE_DOWN_COMMAND: u8 = 141;
+
+// Applies a configuration change live, rather than being restarted.
+pub struct WorkerApplicationTwo {
+    worker: IntervalWorker,
 }
 
-pub struct WorkerApplicationTwo {}
+impl WorkerApplicationTwo {
+    pub fn new(config: WorkerConfig) -> WorkerApplicationTwo {
+        WorkerApplicationTwo { worker: worker("Worker 2", config.interval_secs) }
+    }
+}
 
 impl windows_service_rs_core::application::SimpleApplication for WorkerApplicationTwo {
     fn handle_error(&self, error: &ServiceError) {
         log::error!("Application error: {:?}", error);
     }
 
-    fn run(&self, exit_signal: Arc<AtomicBool>) -> ServiceResult<()> {
-        do_some_work(String::from("Worker 2"), exit_signal);
+    fn initialize(&self, _context: &RunContext) -> ServiceResult<()> {
+        WorkerConfig { interval_secs: self.worker.interval().as_secs() }.validate()
+    }
+
+    fn run_with_context(&self, context: &RunContext) -> ServiceResult<()> {
+        log::info!("Replica {} runs every {:?} - Worker 2", context.replica(), self.worker.interval());
+        do_some_work(&self.worker, |_| { Ok(()) }, context)
+    }
+
+    fn supports_pause(&self) -> bool { true }
+
+    fn supports_live_reload(&self) -> bool { true }
+
+    // The new interval applies from the next tick.
+    fn on_config_changed(&self, change: &ConfigurationChange) -> ServiceResult<()> {
+        let config: WorkerConfig = change.section()?;
+        config.validate()?;
+        log::info!("Configuration is changed ({:?}) - Worker 2", config);
+        self.worker.set_interval(Duration::from_secs(config.interval_secs));
         Ok(())
     }
+
+    fn on_command(&self, command: &UserCommand) -> ServiceResult<()> {
+        match command.code {
+            REPORT_INTERVAL_COMMAND => {
+                log::info!("The interval is {:?} - Worker 2", self.worker.interval());
+                Ok(())
+            },
+            _ => Err(ServiceError::new(format!("Command '{}' is not supported. ", command.name))),
+        }
+    }
 }
 
-fn do_some_work(name: String, exit_signal: Arc<AtomicBool>) {
-    while !exit_signal.load(Ordering::SeqCst) {
-        log::info!("Thread is running - {}", &name);
-        thread::sleep(Duration::from_secs(2));
+// Runs on the Tokio runtime of the host, and receives the work items of Worker 1. It keeps
+// running while the service is paused.
+#[derive(Default)]
+pub struct WorkerApplicationThree {
+    work_items: Mutex<Option<Subscriber<u64>>>,
+}
+
+impl AsyncApplication for WorkerApplicationThree {
+    fn handle_error(&self, error: &ServiceError) {
+        log::error!("Application error: {:?}", error);
     }
-    log::info!("Thread will exit - {}", &name);
-}
\ No newline at end of file
+
+    // Subscribes before Worker 1 runs (it depends on Worker 3), so that no work item is missed.
+    fn initialize(self: Arc<Self>, context: RunContext) -> BoxFuture<ServiceResult<()>> {
+        Box::pin(async move {
+            let work_items = context.message_bus().subscribe::<u64>(WORK_ITEMS_TOPIC, 16, Overflow::Block)?;
+            *self.work_items.lock().unwrap_or_else(|e| e.into_inner()) = Some(work_items);
+            Ok(())
+        })
+    }
+
+    fn run(self: Arc<Self>, context: RunContext) -> BoxFuture<ServiceResult<()>> {
+        Box::pin(async move {
+            let name = "Worker 3";
+            let work_items = self.work_items.lock().unwrap_or_else(|e| e.into_inner()).take()
+                .ok_or_else(|| { ServiceError::new("Worker 3 is not initialized. ") })?;
+            let iterations = context.metrics().counter("work_iterations_total", "The iterations of the work loop.");
+            // The topic is closed once Worker 1 has exited, after the last work item.
+            while let Some(work_item) = work_items.recv_async().await {
+                iterations.inc();
+                log::info!("Work item {} is received - {}", work_item, name);
+            }
+            log::info!("Task will exit ({:?}) - {}", context.stop_reason(), name);
+            Ok(())
+        })
+    }
+}
+
+// The job of the "cleanup" scheduled application. It runs every minute, on a thread of its own,
+// and a run which is still in progress when the next one is due makes the scheduler skip it.
+pub fn clean_up(run: &JobRun, context: &RunContext) -> ServiceResult<()> {
+    log::info!("Clean up the work items due at {} (run #{}) - Cleanup", run.scheduled_at, run.number);
+    let removed = context.metrics().counter("cleanup_removed_total", "The work items removed by the cleanup.");
+    removed.inc();
+    Ok(())
+}
+
+// The workers tick on a fixed cadence, with a little jitter. A tick which takes longer than the
+// interval delays the next one rather than bursting the missed ones.
+fn worker(name: &str, interval_secs: u64) -> IntervalWorker {
+    IntervalWorker::new(Duration::from_secs(interval_secs))
+        .name(name)
+        .jitter(Duration::from_millis(100))
+        .missed_ticks(MissedTicks::Delay)
+        .tick_timeout(Duration::from_secs(10))
+}
+
+fn do_some_work<W>(worker: &IntervalWorker, work: W, context: &RunContext) -> ServiceResult<()>
+    where W: Fn(u64) -> ServiceResult<()> {
+    // The progress is exposed as a metric rather than logged on each iteration.
+    let iterations = context.metrics().counter("work_iterations_total", "The iterations of the work loop.");
+    // Returns at once when the service is stopping.
+    worker.run(context, |_| {
+        iterations.inc();
+        work(iterations.get())
+    })
+}
@@ -1,20 +1,82 @@
-use windows_service_rs_core::application::SimpleApplication;
-use windows_service_rs_core::error::ServiceResult;
-use windows_service_rs_core::service_wrapper;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+use my_business::my_application::{WorkerConfig, REPORT_INTERVAL_COMMAND, SCALE_DOWN_COMMAND, SCALE_UP_COMMAND};
+use windows_service_rs_core::error::ServiceError;
+use windows_service_rs_core::heartbeat::MissedHeartbeat;
+use windows_service_rs_core::registry::ApplicationOptions;
+use windows_service_rs_core::restart_policy::RestartPolicy;
+use windows_service_rs_core::scheduled_application::{JobSchedule, MissedRuns, ScheduledApplication};
+use windows_service_rs_core::service_builder::ServiceBuilder;
+use windows_service_rs_core::service_config::AcceptedControls;
 
-#[cfg(debug_assertions)]
+#[cfg(all(windows, debug_assertions))]
 macro_rules! init_logger {
     () => { windows_service_rs_core::win_dbg_logger::init(); }
 }
 
-#[cfg(not(debug_assertions))]
+#[cfg(not(all(windows, debug_assertions)))]
 macro_rules! init_logger {
     () => { simple_logger::init().unwrap_or_else(|_|{}) }
 }
 
-fn main() -> ServiceResult<()> {
+fn main() {
     init_logger!();
-    let application1_factory:fn() ->Box<dyn SimpleApplication> = || {Box::new(my_business::my_application::WorkerApplicationOne {})};
-    let application2_factory:fn() ->Box<dyn SimpleApplication> = || {Box::new(my_business::my_application::WorkerApplicationTwo {})};
-    service_wrapper::run(vec![application1_factory, application2_factory])
+    // The cleanup runs every minute, and once at the start if a run was missed while the service
+    // was down.
+    let cleanup = JobSchedule::cron("* * * * *")
+        .map(|schedule| {
+            ScheduledApplication::new("cleanup", schedule, my_business::my_application::clean_up)
+                .missed_runs(MissedRuns::RunOnce)
+        })
+        .unwrap_or_else(|e| { exit_on_error(e) });
+    let builder = ServiceBuilder::new();
+    // On unix, SIGUSR1 sends the command which logs the interval of Worker 2 (`sc control
+    // sample-rust-service 130` on Windows).
+    #[cfg(unix)]
+    let builder = builder.user_control_signal(libc::SIGUSR1, REPORT_INTERVAL_COMMAND);
+    // On unix, SIGUSR2 adds a replica of Worker 2 (`sc control sample-rust-service 140` on
+    // Windows, 141 removes one).
+    #[cfg(unix)]
+    let builder = builder.user_control_signal(libc::SIGUSR2, SCALE_UP_COMMAND);
+    // The service name is read from the `--service-name` start argument, the SERVICE_NAME
+    // environment variable or the executable name (sample-rust-service), in that order. The
+    // configuration of the workers is read from the `--config` start argument (E.g.
+    // `--config config.toml`) or the SERVICE_CONFIG environment variable, and it can be
+    // overridden with environment variables, E.g. APP__WORKER_ONE__INTERVAL_SECS=5. The number
+    // of replicas of Worker 2 too, E.g. APP__REPLICAS__WORKER_TWO=3.
+    builder
+        // PARAM_CHANGE reloads the configuration (`sc control sample-rust-service paramchange`, or
+        // SIGHUP on unix).
+        .accepted_controls(AcceptedControls::STOP | AcceptedControls::PAUSE_CONTINUE | AcceptedControls::SHUTDOWN
+            | AcceptedControls::PARAM_CHANGE)
+        .configured_application(
+            // Worker One publishes work items to Worker Three, so it starts after Worker Three and
+            // stops before it: the work items it publishes are all received. It is restarted if
+            // it hangs, its interval must be shorter than the heartbeat timeout.
+            ApplicationOptions::new().name("worker-one").critical(true).restart_policy(RestartPolicy::on_failure())
+                .depends_on("worker-three")
+                .heartbeat(Duration::from_secs(60), MissedHeartbeat::Restart),
+            |config: WorkerConfig| { Box::new(my_business::my_application::WorkerApplicationOne { config }) })
+        // Worker Two starts after Worker One is initialized, and stops before it. It runs two
+        // replicas, each one restarted on its own.
+        .configured_application(
+            ApplicationOptions::new().name("worker-two").depends_on("worker-one")
+                .restart_policy(RestartPolicy::on_failure())
+                .replicas(2).scale_commands(SCALE_UP_COMMAND, SCALE_DOWN_COMMAND)
+                .command("report-interval", REPORT_INTERVAL_COMMAND),
+            |config: WorkerConfig| { Box::new(my_business::my_application::WorkerApplicationTwo::new(config)) })
+        .async_application_with_options(
+            ApplicationOptions::new().name("worker-three"),
+            || { Arc::new(my_business::my_application::WorkerApplicationThree::default()) })
+        .scheduled_application(ApplicationOptions::new(), cleanup)
+        .run()
+        .unwrap_or_else(|e| { exit_on_error(e) });
+}
+
+// E.g. `exit_code::STOP_TIMEOUT` if some applications do not exit in time, or
+// `exit_code::application_failure(0)` if the critical worker fails.
+fn exit_on_error(e: ServiceError) -> ! {
+    log::error!("{}", e);
+    process::exit(e.exit_code as i32);
 }
@@ -1,8 +1,50 @@
+use crate::command::UserCommand;
+use crate::configuration::ConfigurationChange;
 use crate::error::{ServiceError, ServiceResult};
+use crate::health::HealthReport;
+use crate::run_context::RunContext;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 
+// An application implements either `run` or `run_with_context`. `run` only receives the exit
+// signal, while `run_with_context` gets everything the host provides (E.g. the pause signal).
 pub trait SimpleApplication {
     fn handle_error(&self, error:&ServiceError);
-    fn run(&self, exit_signal:Arc<AtomicBool>) -> ServiceResult<()>;
-}
\ No newline at end of file
+
+    /// Called before the service reports Running, E.g. to validate the configuration or to
+    /// connect to a database. If it fails, the service fails to start. When the application is
+    /// restarted, the new instance is initialized again.
+    fn initialize(&self, _context:&RunContext) -> ServiceResult<()> { Ok(()) }
+
+    fn run(&self, _exit_signal:Arc<AtomicBool>) -> ServiceResult<()> {
+        Err(ServiceError::new("The application implements neither run nor run_with_context. "))
+    }
+
+    fn run_with_context(&self, context:&RunContext) -> ServiceResult<()> {
+        self.run(context.exit_signal())
+    }
+
+    /// Returns true if the application calls `PauseSignal::wait_if_paused` from its main loop.
+    /// Applications which do not support pausing keep running while the service is paused.
+    fn supports_pause(&self) -> bool { false }
+
+    /// Returns true if the application applies a configuration change live, with
+    /// `on_config_changed`. Other applications are restarted when their configuration section
+    /// changes, and the new instance gets the new configuration.
+    fn supports_live_reload(&self) -> bool { false }
+
+    /// Called from the service thread when the configuration section of the application
+    /// changes, so it must return quickly. If it fails, the application is restarted.
+    fn on_config_changed(&self, _change:&ConfigurationChange) -> ServiceResult<()> { Ok(()) }
+
+    /// Handles a user-defined command registered with `ApplicationOptions::command`. It is
+    /// called from the command dispatcher thread while the application runs, one command at a
+    /// time. An error is delivered to `handle_error`, the application keeps running.
+    fn on_command(&self, command:&UserCommand) -> ServiceResult<()> {
+        Err(ServiceError::new(format!("The application doesn't handle command '{}'. ", command.name)))
+    }
+
+    /// Polled by the host from another thread while the application runs, so it must return
+    /// quickly (E.g. a cached connection status rather than a query to the database).
+    fn health(&self) -> HealthReport { HealthReport::healthy() }
+}
@@ -0,0 +1,65 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use crate::command::UserCommand;
+use crate::configuration::ConfigurationChange;
+use crate::error::{ServiceError, ServiceResult};
+use crate::health::HealthReport;
+use crate::run_context::RunContext;
+
+/// The future returned by `AsyncApplication::run`.
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+// An async application runs on the Tokio runtime owned by the host, side by side with the
+// `SimpleApplication`s, and it gets the same supervision (restart policy, panic handling, ...).
+// `run` takes the application and the context by value, so that the returned future can be
+// spawned on the runtime. For example:
+//
+// fn run(self: Arc<Self>, context: RunContext) -> BoxFuture<ServiceResult<()>> {
+//     Box::pin(async move {
+//         let token = context.shutdown_token();
+//         while !token.is_cancelled() {
+//             tokio::select! {
+//                 _ = self.do_some_work() => {},
+//                 _ = token.cancelled() => {},
+//             }
+//         }
+//         Ok(())
+//     })
+// }
+//
+// An async application must not call `PauseSignal::wait_if_paused`, which blocks the thread, so
+// it keeps running while the service is paused.
+pub trait AsyncApplication: Send + Sync {
+    fn handle_error(&self, error: &ServiceError);
+
+    /// The async counterpart of `SimpleApplication::initialize`.
+    fn initialize(self: Arc<Self>, _context: RunContext) -> BoxFuture<ServiceResult<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn run(self: Arc<Self>, context: RunContext) -> BoxFuture<ServiceResult<()>>;
+
+    /// Please refer to `SimpleApplication::health`. It is called outside of the runtime, so it
+    /// must not block on a future.
+    fn health(&self) -> HealthReport {
+        HealthReport::healthy()
+    }
+
+    /// Please refer to `SimpleApplication::supports_live_reload`.
+    fn supports_live_reload(&self) -> bool {
+        false
+    }
+
+    /// Please refer to `SimpleApplication::on_config_changed`. It is called outside of the
+    /// runtime, so it must not block on a future.
+    fn on_config_changed(&self, _change: &ConfigurationChange) -> ServiceResult<()> {
+        Ok(())
+    }
+
+    /// Please refer to `SimpleApplication::on_command`. It is called outside of the runtime,
+    /// so it must not block on a future (it may spawn one on the runtime).
+    fn on_command(&self, command: &UserCommand) -> ServiceResult<()> {
+        Err(ServiceError::new(format!("The application doesn't handle command '{}'. ", command.name)))
+    }
+}
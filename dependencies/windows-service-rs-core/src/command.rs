@@ -0,0 +1,182 @@
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::registry::RegisteredApplication;
+use crate::supervisor::ReplicaSet;
+
+/// The lowest control code of the user-defined commands. Windows reserves the codes below it.
+pub const USER_CONTROL_MIN: u8 = 128;
+/// The highest control code of the user-defined commands.
+pub const USER_CONTROL_MAX: u8 = 255;
+
+const STOP_POLLING_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A user-defined command of an application, E.g. "flush-cache" on control code 130. Please
+/// refer to `ApplicationOptions::command`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserCommand {
+    pub name: String,
+    pub code: u8,
+}
+
+impl UserCommand {
+    pub fn new<N: Into<String>>(name: N, code: u8) -> UserCommand {
+        UserCommand { name: name.into(), code }
+    }
+}
+
+/// The control codes which scale an application, please refer to
+/// `ApplicationOptions::scale_commands`. They are handled by the host rather than the
+/// application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScaleCommands {
+    /// Starts one more replica.
+    pub up: u8,
+    /// Stops the last replica.
+    pub down: u8,
+}
+
+// Checks the commands of an application about to be registered against the commands of the
+// registered applications: a control code has one owner, so that the host knows where to
+// dispatch it.
+pub(crate) fn check_commands(registered: &[RegisteredApplication], application: &RegisteredApplication) -> ServiceResult<()> {
+    let commands = all_commands(application);
+    for (index, command) in commands.iter().enumerate() {
+        if command.code < USER_CONTROL_MIN {
+            return Err(ServiceError::of_kind(ErrorKind::Config, format!(
+                "The control code {} of command '{}' is reserved, the user-defined codes are {} to {}. ",
+                command.code, command.name, USER_CONTROL_MIN, USER_CONTROL_MAX)));
+        }
+        let taken = commands[..index].iter().any(|other| other.code == command.code)
+            || registered.iter().any(|other| all_commands(other).iter().any(|other| other.code == command.code));
+        if taken {
+            return Err(ServiceError::of_kind(ErrorKind::Config, format!("The control code {} is already registered. ", command.code)));
+        }
+    }
+    Ok(())
+}
+
+// The user-defined commands of the application, and the scale commands which take control
+// codes as well.
+fn all_commands(application: &RegisteredApplication) -> Vec<UserCommand> {
+    let options = application.options();
+    let mut commands = options.commands.clone();
+    if let Some(scale) = options.scale_commands {
+        commands.push(UserCommand::new("scale-up", scale.up));
+        commands.push(UserCommand::new("scale-down", scale.down));
+    }
+    commands
+}
+
+// Returns the application scaled by the control code, and whether it is scaled up.
+pub(crate) fn find_scale_command(applications: &[RegisteredApplication], code: u8) -> Option<(usize, bool)> {
+    applications.iter().enumerate().find_map(|(index, application)| {
+        application.options().scale_commands.and_then(|scale| {
+            match code {
+                code if code == scale.up => Some((index, true)),
+                code if code == scale.down => Some((index, false)),
+                _ => None,
+            }
+        })
+    })
+}
+
+// The user-defined commands are sent by the service manager (E.g. `sc control <service> 130` or
+// `service-installer control --name <service> --code 130` on Windows, a signal mapped by
+// `ServiceBuilder::user_control_signal` on unix) and handled by the application which
+// registers the control code, with `SimpleApplication::on_command`. Each running replica of the
+// application handles the command.
+//
+// The host doesn't handle the commands on the thread which receives the controls: a command
+// may take a while (E.g. flushing a cache to the disk), and the control handler of the Windows
+// SCM must return at once. So the commands are queued to the dispatcher thread, which handles
+// them one after another. A command received while its application is not running (E.g. it is
+// being restarted) is dropped.
+//
+// The scale commands don't go through the dispatcher, the host handles them.
+pub(crate) struct CommandDispatcher {
+    owners: Arc<Vec<CommandOwner>>,
+    sender: Option<Sender<u8>>,
+    handle: JoinHandle<()>,
+}
+
+struct CommandOwner {
+    index: usize,
+    application: RegisteredApplication,
+    commands: Vec<UserCommand>,
+    replicas: ReplicaSet,
+}
+
+impl CommandOwner {
+    fn command(&self, code: u8) -> Option<&UserCommand> {
+        self.commands.iter().find(|command| command.code == code)
+    }
+}
+
+impl CommandDispatcher {
+    pub(crate) fn start(applications: &[RegisteredApplication], replicas: &[ReplicaSet]) -> CommandDispatcher {
+        let owners: Arc<Vec<CommandOwner>> = Arc::new(applications.iter().zip(replicas).enumerate()
+            .filter(|(_, (application, _))| !application.options().commands.is_empty())
+            .map(|(index, (application, replicas))| CommandOwner {
+                index,
+                application: application.clone(),
+                commands: application.options().commands.clone(),
+                replicas: replicas.clone(),
+            })
+            .collect());
+        let (sender, receiver) = mpsc::channel::<u8>();
+        let owners_for_thread = owners.clone();
+        let handle = thread::spawn(move || {
+            for code in receiver {
+                let (owner, command) = match find(&owners_for_thread, code) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                let live = owner.replicas.live();
+                if live.is_empty() {
+                    log::warn!("{} is not running, command '{}' is dropped.", owner.application.identity(owner.index), command.name);
+                }
+                for (replica, live) in live {
+                    live.handle_command(&owner.application.replica_identity(owner.index, replica), command);
+                }
+            }
+        });
+        CommandDispatcher { owners, sender: Some(sender), handle }
+    }
+
+    // Queues the command of the control code. Returns false if no application registers it.
+    pub(crate) fn dispatch(&self, code: u8) -> bool {
+        match (find(&self.owners, code), &self.sender) {
+            (Some((owner, command)), Some(sender)) => {
+                log::info!("Control code {} received, queue command '{}' of {}.", code, command.name, owner.application.identity(owner.index));
+                sender.send(code).unwrap_or_default();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // It is stopped once the applications have exited, so the commands still queued are
+    // dropped. A command handler still running after the timeout is abandoned, like the
+    // applications are.
+    pub(crate) fn stop(mut self, timeout: Duration) {
+        self.sender.take();
+        let deadline = Instant::now() + timeout;
+        while !self.handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(STOP_POLLING_INTERVAL);
+        }
+        if !self.handle.is_finished() {
+            log::error!("The commands are not handled before the stop deadline and are abandoned.");
+            return;
+        }
+        self.handle.join().unwrap_or_else(|e| {
+            log::error!("Command dispatcher error: {:?}", e);
+        });
+    }
+}
+
+fn find(owners: &[CommandOwner], code: u8) -> Option<(&CommandOwner, &UserCommand)> {
+    owners.iter().find_map(|owner| { owner.command(code).map(|command| (owner, command)) })
+}
@@ -0,0 +1,342 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+
+/// Passes the configuration file as a start argument: `--config <path>`.
+pub const CONFIG_ARGUMENT: &str = "--config";
+/// Passes the configuration file through the environment.
+pub const CONFIG_ENVIRONMENT_VARIABLE: &str = "SERVICE_CONFIG";
+/// The prefix of the environment overrides, E.g. `APP__WORKER_ONE__INTERVAL_SECS=5`.
+pub const DEFAULT_ENVIRONMENT_PREFIX: &str = "APP";
+/// The section which gives the number of replicas of the applications, E.g.
+/// `[replicas] worker-one = 3`. No application can have this name.
+pub const REPLICAS_SECTION: &str = "replicas";
+
+// The separator of the sections and keys in the name of an environment override.
+const OVERRIDE_SEPARATOR: &str = "__";
+
+/// The format of a configuration file, told by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+    /// Requires the `yaml` feature.
+    #[cfg(feature = "yaml")]
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> ServiceResult<ConfigFormat> {
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("json") => Ok(ConfigFormat::Json),
+            #[cfg(feature = "yaml")]
+            Some("yaml") | Some("yml") => Ok(ConfigFormat::Yaml),
+            _ => Err(ServiceError::of_kind(ErrorKind::Config, format!("The format of configuration file {} is not supported. ", path.display()))),
+        }
+    }
+}
+
+// The configuration of the service: a tree of sections, one per named application, read from a
+// TOML (or JSON, or YAML with the `yaml` feature) file. For example:
+//
+// [worker-one]
+// interval_secs = 5
+//
+// [worker-one.database]
+// url = "postgres://localhost/orders"
+//
+// The file is given by the `--config` start argument or the SERVICE_CONFIG environment
+// variable, please refer to `ServiceConfig::config_file`. Without a file, the configuration
+// only holds the environment overrides.
+//
+// An environment variable named PREFIX__SECTION__KEY overrides (or adds) a key, where the
+// prefix is `APP` by default. The names are matched ignoring the case, and `_` matches `-`, so
+// `APP__WORKER_ONE__DATABASE__URL` overrides `url` in `[worker-one.database]`. The value keeps
+// the type of the value it overrides, a new value is a boolean, a number, a JSON array or
+// object if it parses as one, and a string otherwise.
+//
+// The host deserializes the section of each configured application when it creates the
+// application, so an invalid section fails the start of the service. Please refer to
+// `ApplicationRegistry::register_configured`.
+//
+// The configuration is reloaded on the ParamChange control (SIGHUP on unix). The reloaded
+// configuration only takes effect if the sections of all the configured applications are
+// valid. Then the running applications whose section has changed apply it live (please refer
+// to `SimpleApplication::on_config_changed`), or they are restarted with it.
+//
+// The `[replicas]` section is not the section of an application: it gives the number of
+// replicas of the applications, please refer to `ApplicationOptions::replicas`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Configuration {
+    root: Value,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration { root: Value::Object(Map::new()) }
+    }
+}
+
+impl Configuration {
+    pub fn new() -> Configuration {
+        Configuration::default()
+    }
+
+    /// Reads the file (if any), and applies the overrides of the process environment.
+    pub fn load(path: Option<&Path>, environment_prefix: &str) -> ServiceResult<Configuration> {
+        let configuration = match path {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|e| {
+                    ServiceError::caused_by(e, &format!("Fail to read configuration file {}. ", path.display())).with_kind(ErrorKind::Config)
+                })?;
+                let configuration = Configuration::parse(&text, ConfigFormat::from_path(path)?)?;
+                log::info!("Configuration is loaded from {}.", path.display());
+                configuration
+            },
+            None => Configuration::new(),
+        };
+        Ok(configuration.with_overrides(environment_prefix, unicode_variables(environment_prefix)))
+    }
+
+    pub fn parse(text: &str, format: ConfigFormat) -> ServiceResult<Configuration> {
+        let root: Value = match format {
+            ConfigFormat::Toml => toml::from_str(text)
+                // The error shows the line in error, so it spans several lines.
+                .map_err(|e| { ServiceError::of_kind(ErrorKind::Config, format!("The TOML configuration is invalid. {}", e)) })?,
+            ConfigFormat::Json => serde_json::from_str(text)
+                .map_err(|e| { ServiceError::of_kind(ErrorKind::Config, format!("The JSON configuration is invalid: {}. ", e)) })?,
+            #[cfg(feature = "yaml")]
+            ConfigFormat::Yaml => serde_yaml::from_str(text)
+                .map_err(|e| { ServiceError::of_kind(ErrorKind::Config, format!("The YAML configuration is invalid: {}. ", e)) })?,
+        };
+        match root {
+            Value::Object(_) => Ok(Configuration { root }),
+            // An empty YAML document is null.
+            Value::Null => Ok(Configuration::new()),
+            _ => Err(ServiceError::of_kind(ErrorKind::Config, "The configuration must be a table of sections. ")),
+        }
+    }
+
+    /// Applies the overrides among the given variables, E.g. `std::env::vars()`.
+    pub fn with_overrides<I>(mut self, prefix: &str, variables: I) -> Configuration
+        where I: IntoIterator<Item = (String, String)> {
+        let prefix = format!("{}{}", prefix, OVERRIDE_SEPARATOR);
+        let mut overrides: Vec<(String, String)> = variables.into_iter()
+            .filter(|(name, _)| { override_key(name, &prefix).is_some() })
+            .collect();
+        // The parents are overridden before their keys, whatever the order of the environment.
+        overrides.sort();
+        for (name, value) in overrides {
+            let path: Vec<&str> = override_key(&name, &prefix).unwrap_or_default().split(OVERRIDE_SEPARATOR).collect();
+            if path.iter().any(|key| key.is_empty()) {
+                log::warn!("Environment variable {} is not a valid configuration override.", name);
+                continue;
+            }
+            // The value is not logged, it may be a secret.
+            log::info!("Configuration {} is overridden by environment variable {}.", path.join(".").to_lowercase(), name);
+            set(&mut self.root, &path, &value);
+        }
+        self
+    }
+
+    pub fn has_section(&self, name: &str) -> bool {
+        self.root.as_object().and_then(|sections| find(sections, name)).is_some()
+    }
+
+    /// Whether the section differs in the other configuration, E.g. the reloaded one.
+    pub fn section_changed(&self, other: &Configuration, name: &str) -> bool {
+        let section = |configuration: &Configuration| {
+            configuration.root.as_object().and_then(|sections| find(sections, name)).map(|(_, section)| section.clone())
+        };
+        section(self) != section(other)
+    }
+
+    /// Deserializes the section. A missing section is deserialized from an empty table, so the
+    /// defaults of the type (E.g. `#[serde(default)]`) apply.
+    pub fn section<C: DeserializeOwned>(&self, name: &str) -> ServiceResult<C> {
+        let section = self.root.as_object()
+            .and_then(|sections| find(sections, name))
+            .map(|(_, section)| section.clone())
+            .unwrap_or_else(|| { Value::Object(Map::new()) });
+        serde_json::from_value(section)
+            .map_err(|e| { ServiceError::of_kind(ErrorKind::Config, format!("The configuration section '{}' is invalid: {}. ", name, e)) })
+    }
+
+    /// The number of replicas of the application given by the `replicas` section, which
+    /// overrides the one given by `ApplicationOptions::replicas`. None if the section doesn't
+    /// give it.
+    pub fn replicas(&self, name: &str) -> ServiceResult<Option<usize>> {
+        let value = self.root.as_object()
+            .and_then(|sections| find(sections, REPLICAS_SECTION))
+            .and_then(|(_, section)| section.as_object())
+            .and_then(|section| find(section, name))
+            .map(|(_, value)| value);
+        match value {
+            None => Ok(None),
+            Some(value) => value.as_u64().filter(|replicas| *replicas > 0).map(|replicas| Some(replicas as usize)).ok_or_else(|| {
+                ServiceError::of_kind(ErrorKind::Config, format!("The number of replicas of '{}' must be a number from 1, not {}. ", name, value))
+            }),
+        }
+    }
+
+    pub(crate) fn is_reserved_section(name: &str) -> bool {
+        normalize(name) == normalize(REPLICAS_SECTION)
+    }
+}
+
+/// The reloaded configuration, as delivered to `SimpleApplication::on_config_changed`.
+pub struct ConfigurationChange<'a> {
+    configuration: &'a Configuration,
+    section: &'a str,
+}
+
+impl<'a> ConfigurationChange<'a> {
+    pub(crate) fn new(configuration: &'a Configuration, section: &'a str) -> ConfigurationChange<'a> {
+        ConfigurationChange { configuration, section }
+    }
+
+    /// The name of the changed section, which is the name of the application.
+    pub fn section_name(&self) -> &str {
+        self.section
+    }
+
+    /// Deserializes the new section of the application.
+    pub fn section<C: DeserializeOwned>(&self) -> ServiceResult<C> {
+        self.configuration.section(self.section)
+    }
+
+    pub fn configuration(&self) -> &Configuration {
+        self.configuration
+    }
+}
+
+// The configuration in effect, shared by the host and the run contexts. A reload replaces it as
+// a whole.
+pub(crate) type SharedConfiguration = Arc<RwLock<Arc<Configuration>>>;
+
+// The keys are matched ignoring the case, and `_` matches `-`, since the environment variables
+// can't have `-` in their names on most shells.
+fn normalize(key: &str) -> String {
+    key.to_lowercase().replace('-', "_")
+}
+
+fn find<'a>(table: &'a Map<String, Value>, key: &str) -> Option<(&'a String, &'a Value)> {
+    let key = normalize(key);
+    table.iter().find(|(existing, _)| { normalize(existing) == key })
+}
+
+fn set(node: &mut Value, path: &[&str], raw: &str) {
+    if !node.is_object() {
+        *node = Value::Object(Map::new());
+    }
+    let table = match node.as_object_mut() {
+        Some(table) => table,
+        None => return,
+    };
+    let key = find(table, path[0]).map(|(existing, _)| existing.clone())
+        .unwrap_or_else(|| { path[0].to_lowercase() });
+    if path.len() == 1 {
+        let value = convert(table.get(&key), raw);
+        table.insert(key, value);
+    } else {
+        set(table.entry(key).or_insert(Value::Null), &path[1..], raw);
+    }
+}
+
+// The key path of the override, which follows the prefix (ignoring the ASCII case) in the name
+// of the variable. The prefix is compared byte for byte, since changing the case of the whole
+// name may change its length.
+fn override_key<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    name.get(..prefix.len())
+        .filter(|name_prefix| { name_prefix.eq_ignore_ascii_case(prefix) })
+        .map(|_| &name[prefix.len()..])
+}
+
+// The variables of the process environment. `env::vars` panics on a variable which is not valid
+// Unicode, such a variable is skipped instead, with a warning if it looks like an override.
+fn unicode_variables(prefix: &str) -> Vec<(String, String)> {
+    let prefix = format!("{}{}", prefix, OVERRIDE_SEPARATOR);
+    env::vars_os()
+        .filter_map(|(name, value)| match (name.into_string(), value.into_string()) {
+            (Ok(name), Ok(value)) => Some((name, value)),
+            (name, _) => {
+                let name = name.unwrap_or_else(|name| name.to_string_lossy().into_owned());
+                if override_key(&name, &prefix).is_some() {
+                    log::warn!("Environment variable {} is not valid Unicode, it is not a configuration override.", name);
+                }
+                None
+            },
+        })
+        .collect()
+}
+
+// Converts the raw value of an environment variable, please refer to `Configuration`.
+fn convert(existing: Option<&Value>, raw: &str) -> Value {
+    if let Some(Value::String(_)) = existing {
+        return Value::String(String::from(raw));
+    }
+    let trimmed = raw.trim();
+    if trimmed.eq_ignore_ascii_case("true") || trimmed.eq_ignore_ascii_case("false") {
+        return Value::Bool(trimmed.eq_ignore_ascii_case("true"));
+    }
+    if let Ok(number) = trimmed.parse::<i64>() {
+        return Value::from(number);
+    }
+    if let Ok(number) = trimmed.parse::<f64>() {
+        if number.is_finite() {
+            return Value::from(number);
+        }
+    }
+    if trimmed.starts_with('[') || trimmed.starts_with('{') {
+        if let Ok(value) = serde_json::from_str(trimmed) {
+            return value;
+        }
+    }
+    Value::String(String::from(raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::{ConfigFormat, Configuration};
+
+    fn variables(variables: &[(&str, &str)]) -> Vec<(String, String)> {
+        variables.iter().map(|(name, value)| (String::from(*name), String::from(*value))).collect()
+    }
+
+    #[test]
+    fn overrides_nested_keys() {
+        let configuration = Configuration::parse("[worker-one]\ninterval_secs = 5\nname = \"one\"\n", ConfigFormat::Toml).unwrap()
+            .with_overrides("APP", variables(&[
+                // The child comes first, yet the parent doesn't replace it.
+                ("APP__WORKER_ONE__DATABASE__URL", "postgres://localhost/orders"),
+                ("APP__WORKER_ONE__DATABASE", "{\"pool\": 3}"),
+                ("app__worker_one__interval_secs", "10"),
+                ("APP__WORKER_ONE__NAME", "42"),
+                ("APP__WORKER_ONE__", "ignored"),
+                ("OTHER__WORKER_ONE__NAME", "ignored"),
+            ]));
+
+        assert_eq!(configuration.section::<serde_json::Value>("worker-one").unwrap(), json!({
+            "interval_secs": 10,
+            "name": "42",
+            "database": { "pool": 3, "url": "postgres://localhost/orders" },
+        }));
+    }
+
+    #[test]
+    fn matches_the_prefix_ignoring_the_ascii_case_only() {
+        // The upper case of 'ı' is 'I', which is shorter in UTF-8.
+        let configuration = Configuration::new()
+            .with_overrides("III", variables(&[("ııı__X", "1"), ("iii__Y", "2")]));
+
+        assert!(!configuration.has_section("x"));
+        assert_eq!(configuration.section::<serde_json::Value>("y").unwrap(), json!(2));
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::registry::RegisteredApplication;
+
+// The dependencies between the registered applications, by index. An application is initialized
+// after all its dependencies are initialized, and it is stopped before them.
+//
+// The applications are stopped in stages. The stop stage of an application is the length of the
+// longest chain of applications depending on it: the applications nobody depends on are in stage
+// 0 and stop first, then the applications they depend on, and so on. Without dependencies, all
+// the applications are in stage 0 and stop together.
+pub(crate) struct DependencyGraph {
+    dependencies: Vec<Vec<usize>>,
+    stop_stages: Vec<usize>,
+}
+
+impl DependencyGraph {
+    // Fails if an application depends on an application which is not registered, or if the
+    // dependencies form a cycle.
+    pub(crate) fn new(applications: &[RegisteredApplication]) -> ServiceResult<DependencyGraph> {
+        let indexes = indexes_by_name(applications);
+        let mut dependencies = vec![];
+        for (index, application) in applications.iter().enumerate() {
+            let mut resolved = vec![];
+            for dependency in &application.options().dependencies {
+                let dependency_index = indexes.get(dependency.as_str()).ok_or_else(|| {
+                    ServiceError::of_kind(ErrorKind::Config, format!(
+                        "{} depends on '{}', which is not registered. ", application.identity(index), dependency))
+                })?;
+                resolved.push(*dependency_index);
+            }
+            dependencies.push(resolved);
+        }
+        if let Some(cycle) = find_cycle(applications) {
+            return Err(cycle_error(&cycle));
+        }
+
+        let mut stop_stages = vec![None; applications.len()];
+        for index in 0..applications.len() {
+            stop_stage_of(index, &dependencies, &mut stop_stages);
+        }
+        let stop_stages = stop_stages.into_iter().map(|stage| stage.unwrap_or_default()).collect();
+        Ok(DependencyGraph { dependencies, stop_stages })
+    }
+
+    pub(crate) fn dependencies(&self, index: usize) -> &[usize] {
+        &self.dependencies[index]
+    }
+
+    pub(crate) fn stop_stage(&self, index: usize) -> usize {
+        self.stop_stages[index]
+    }
+}
+
+// Checks the dependencies when an application is registered. The dependencies which are not
+// registered yet are skipped, since they may be registered later.
+pub(crate) fn check_cycles(applications: &[RegisteredApplication]) -> ServiceResult<()> {
+    match find_cycle(applications) {
+        Some(cycle) => Err(cycle_error(&cycle)),
+        None => Ok(()),
+    }
+}
+
+fn cycle_error(cycle: &[String]) -> ServiceError {
+    ServiceError::of_kind(ErrorKind::Config, format!("The application dependencies form a cycle: {}. ", cycle.join(" -> ")))
+}
+
+fn indexes_by_name(applications: &[RegisteredApplication]) -> HashMap<&str, usize> {
+    applications.iter().enumerate()
+        .filter_map(|(index, application)| { application.name().map(|name| (name, index)) })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    NotVisited,
+    InProgress,
+    Done,
+}
+
+// Returns the names along the first cycle found, E.g. ["a", "b", "a"].
+fn find_cycle(applications: &[RegisteredApplication]) -> Option<Vec<String>> {
+    let indexes = indexes_by_name(applications);
+    let dependencies: Vec<Vec<usize>> = applications.iter()
+        .map(|application| {
+            application.options().dependencies.iter()
+                .filter_map(|dependency| indexes.get(dependency.as_str()).copied())
+                .collect()
+        })
+        .collect();
+
+    let mut visits = vec![Visit::NotVisited; applications.len()];
+    let mut path = vec![];
+    (0..applications.len()).find_map(|index| { visit(index, &dependencies, &mut visits, &mut path) })
+        .map(|cycle| {
+            cycle.into_iter()
+                .map(|index| { applications[index].name().unwrap_or_default().to_string() })
+                .collect()
+        })
+}
+
+// A depth first search, the path holds the applications being visited. Reaching an application
+// which is in progress means there is a cycle, from that application to the end of the path.
+fn visit(index: usize, dependencies: &[Vec<usize>], visits: &mut [Visit], path: &mut Vec<usize>) -> Option<Vec<usize>> {
+    match visits[index] {
+        Visit::Done => return None,
+        Visit::InProgress => {
+            let start = path.iter().position(|visited| *visited == index).unwrap_or_default();
+            let mut cycle = path[start..].to_vec();
+            cycle.push(index);
+            return Some(cycle);
+        },
+        Visit::NotVisited => {},
+    }
+    visits[index] = Visit::InProgress;
+    path.push(index);
+    for dependency in &dependencies[index] {
+        if let Some(cycle) = visit(*dependency, dependencies, visits, path) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    visits[index] = Visit::Done;
+    None
+}
+
+// The graph has no cycle, so the recursion ends. An application is stopped one stage after the
+// last application depending on it.
+fn stop_stage_of(index: usize, dependencies: &[Vec<usize>], stop_stages: &mut [Option<usize>]) -> usize {
+    if let Some(stage) = stop_stages[index] {
+        return stage;
+    }
+    let stage = (0..dependencies.len())
+        .filter(|dependent| dependencies[*dependent].contains(&index))
+        .map(|dependent| stop_stage_of(dependent, dependencies, stop_stages) + 1)
+        .max()
+        .unwrap_or_default();
+    stop_stages[index] = Some(stage);
+    stage
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::application::SimpleApplication;
+    use crate::error::{ErrorKind, ServiceError};
+    use crate::registry::{ApplicationOptions, ApplicationRegistry};
+    use super::DependencyGraph;
+
+    struct IdleApplication {}
+
+    impl SimpleApplication for IdleApplication {
+        fn handle_error(&self, _error: &ServiceError) {}
+    }
+
+    fn registry(applications: &[(&str, &[&str])]) -> ApplicationRegistry {
+        let mut registry = ApplicationRegistry::new();
+        for (name, dependencies) in applications {
+            let options = dependencies.iter().fold(ApplicationOptions::new().name(*name), |options, dependency| options.depends_on(*dependency));
+            registry.register_with_options(options, Box::new(|| { Box::new(IdleApplication {}) })).unwrap();
+        }
+        registry
+    }
+
+    #[test]
+    fn stops_the_dependents_first() {
+        let registry = registry(&[("a", &["b"]), ("b", &["c"]), ("c", &[]), ("d", &[])]);
+        let graph = DependencyGraph::new(registry.applications()).unwrap();
+
+        assert_eq!(graph.dependencies(0), &[1]);
+        assert_eq!(graph.dependencies(2), &[] as &[usize]);
+        assert_eq!((0..4).map(|index| graph.stop_stage(index)).collect::<Vec<_>>(), vec![0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn fails_on_a_missing_dependency() {
+        // The dependency may be registered later, so the registration succeeds.
+        let registry = registry(&[("a", &["missing"])]);
+        let error = DependencyGraph::new(registry.applications()).err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::Config);
+        assert!(error.to_string().contains("'missing'"), "{}", error);
+    }
+}
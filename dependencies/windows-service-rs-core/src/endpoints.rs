@@ -0,0 +1,169 @@
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use crate::error::ServiceResult;
+use crate::health_monitor::HealthMonitor;
+use crate::http_server::{HttpResponse, HttpServer};
+use crate::metrics::MetricsRegistry;
+use crate::service_config::ServiceConfig;
+use crate::shutdown_token::ShutdownToken;
+
+// The local HTTP endpoints of the service, they run until the service stops:
+//
+// (1) `/healthz` and `/readyz` on the health port, please refer to `health_monitor`.
+// (2) `/metrics` on the metrics port, the metrics in the Prometheus text format.
+//
+// The health port and the metrics port may be the same, then one server serves all the
+// endpoints.
+pub(crate) struct Endpoints {
+    servers: Vec<HttpServer>,
+    token: ShutdownToken,
+    poller: Option<JoinHandle<()>>,
+}
+
+impl Endpoints {
+    pub(crate) fn start(config: &ServiceConfig, health: Option<Arc<HealthMonitor>>, metrics: MetricsRegistry) -> ServiceResult<Endpoints> {
+        let started = Instant::now();
+        let uptime = metrics.gauge("service_uptime_seconds", "The time since the service started, in seconds.");
+        let mut ports: Vec<u16> = config.health_port.iter().chain(config.metrics_port.iter()).copied().collect();
+        ports.dedup();
+
+        let mut servers = vec![];
+        for port in ports {
+            let health = health.clone().filter(|_| { config.health_port == Some(port) });
+            let metrics = Some(metrics.clone()).filter(|_| { config.metrics_port == Some(port) });
+            let uptime = uptime.clone();
+            let server = HttpServer::start(port, Box::new(move |path| {
+                health.as_ref().and_then(|health| { health.respond(path) })
+                    .or_else(|| {
+                        let metrics = metrics.as_ref().filter(|_| { path == "/metrics" })?;
+                        uptime.set(started.elapsed().as_secs_f64());
+                        Some(HttpResponse {
+                            status: 200,
+                            content_type: "text/plain; version=0.0.4; charset=utf-8",
+                            body: metrics.render(),
+                        })
+                    })
+                    .unwrap_or_else(|| { HttpResponse::text(404, "Not Found") })
+            }));
+            match server {
+                Ok(server) => servers.push(server),
+                Err(e) => {
+                    servers.into_iter().for_each(|server| server.stop());
+                    return Err(e);
+                },
+            }
+        }
+
+        let token = ShutdownToken::new();
+        let poller = health.map(|health| {
+            let token = token.clone();
+            let interval = config.health_check_interval;
+            thread::spawn(move || {
+                health.poll();
+                while token.sleep_or_cancel(interval) {
+                    health.poll();
+                }
+            })
+        });
+        Ok(Endpoints { servers, token, poller })
+    }
+
+    pub(crate) fn stop(self) {
+        self.token.cancel();
+        self.servers.into_iter().for_each(|server| server.stop());
+        if let Some(poller) = self.poller {
+            poller.join().unwrap_or_else(|e| {
+                log::error!("Health monitor error: {:?}", e);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::sync::Arc;
+    use serde_json::Value;
+    use crate::application::SimpleApplication;
+    use crate::error::{ErrorKind, ServiceError, ServiceResult};
+    use crate::health_monitor::HealthMonitor;
+    use crate::host::{HostState, HostStatus, StatusReporter};
+    use crate::metrics::MetricsRegistry;
+    use crate::registry::{ApplicationOptions, ApplicationRegistry};
+    use crate::service_config::ServiceConfig;
+    use crate::supervisor::ReplicaSet;
+    use super::Endpoints;
+
+    struct IdleApplication {}
+
+    impl SimpleApplication for IdleApplication {
+        fn handle_error(&self, _error: &ServiceError) {}
+    }
+
+    struct SilentReporter {}
+
+    impl StatusReporter for SilentReporter {
+        fn report(&self, _status: HostStatus) -> ServiceResult<()> {
+            Ok(())
+        }
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap().port()
+    }
+
+    // Returns the status code and the body of the response.
+    fn get(port: u16, path: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap_or_default();
+        (status, String::from(body))
+    }
+
+    #[test]
+    fn serves_the_health_on_loopback() {
+        let mut registry = ApplicationRegistry::new();
+        registry.register_with_options(ApplicationOptions::new().name("cache"), Box::new(|| {
+            Box::new(IdleApplication {}) as Box<dyn SimpleApplication + Send + Sync>
+        })).unwrap();
+        let replicas = vec![ReplicaSet::default()];
+        replicas[0].expect(0, true);
+        let health = Arc::new(HealthMonitor::new(registry.applications(), &replicas));
+        let reporter = health.track(Arc::new(SilentReporter {}));
+        let config = ServiceConfig { health_port: Some(free_port()), ..ServiceConfig::default() };
+        let port = config.health_port.unwrap();
+        let endpoints = Endpoints::start(&config, Some(health.clone()), MetricsRegistry::new()).unwrap();
+
+        // The application is starting: the service is alive, but not ready.
+        health.poll();
+        let (status, body) = get(port, "/healthz");
+        assert_eq!(status, 200);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["state"], "StartPending");
+        assert_eq!(body["applications"][0]["name"], "cache");
+        assert_eq!(body["applications"][0]["detail"], "The application is starting.");
+        assert_eq!(get(port, "/readyz").0, 503);
+
+        // The service runs, but the application doesn't.
+        reporter.report(HostStatus::new(HostState::Running)).unwrap();
+        health.poll();
+        let (status, body) = get(port, "/healthz");
+        assert_eq!(status, 503);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["applications"][0]["detail"], "The application is not running.");
+        assert_eq!(get(port, "/metrics").0, 404);
+
+        // The port is taken while the endpoints run.
+        let error = Endpoints::start(&config, Some(health.clone()), MetricsRegistry::new()).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Io);
+        endpoints.stop();
+    }
+}
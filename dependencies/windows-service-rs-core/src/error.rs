@@ -1,34 +1,207 @@
+use std::error::Error;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
+use std::io;
+use crate::exit_code;
 
-pub struct ServiceError { pub message: String }
+/// What kind of failure an error is. The kind gives the exit code of the service if the error
+/// stops it, please refer to `ErrorKind::exit_code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The configuration is missing or invalid, E.g. a section which doesn't deserialize.
+    Config,
+    /// An I/O operation fails, E.g. reading a file or listening on a port.
+    Io,
+    /// The service manager or the operating system rejects a call, E.g. the SCM dispatcher or
+    /// the systemd notification socket.
+    Platform,
+    /// An application fails.
+    Application,
+    /// Something doesn't complete in time.
+    Timeout,
+    /// The operation is cancelled, E.g. because the service is stopping.
+    Cancelled,
+    /// Anything else.
+    Other,
+}
+
+impl ErrorKind {
+    /// The exit code of the service if an error of this kind stops it. The failures of the
+    /// applications and the other errors have the generic `exit_code::FAILURE`, so that the
+    /// host can tell which application fails (please refer to `exit_code::application_failure`).
+    pub fn exit_code(self) -> u32 {
+        match self {
+            ErrorKind::Config => exit_code::CONFIG_ERROR,
+            ErrorKind::Io => exit_code::IO_ERROR,
+            ErrorKind::Platform => exit_code::PLATFORM_ERROR,
+            ErrorKind::Timeout => exit_code::TIMEOUT,
+            ErrorKind::Cancelled => exit_code::CANCELLED,
+            ErrorKind::Application | ErrorKind::Other => exit_code::FAILURE,
+        }
+    }
+}
+
+// The error of the service and of the applications. Besides its message, an error has:
+//
+// (1) A kind, which tells a configuration error from an I/O error or a timeout, E.g.
+//     `ServiceError::new("The interval must not be zero. ").with_kind(ErrorKind::Config)`.
+//     The kind gives the exit code of the service, unless `with_exit_code` sets another one.
+// (2) The error which causes it, if any, which `source()` returns. `caused_by` keeps the cause
+//     as the source, `with` only keeps its debug output in the message (any `Debug` value can
+//     be given to `with`, E.g. a poisoned lock which borrows the mutex).
+// (3) A context, E.g. `.with_context("application", "worker-one").with_context("phase",
+//     "initialize")`, which is shown after the message.
+//
+// An I/O error converts to an error of kind `Io`, and so does a `windows_service::Error` to an
+// error of kind `Platform` on Windows, so `?` works on them in a function returning
+// `ServiceResult`.
+pub struct ServiceError {
+    pub message: String,
+    pub exit_code: u32,
+    kind: ErrorKind,
+    source: Option<Box<dyn Error + Send + Sync + 'static>>,
+    context: Vec<(String, String)>,
+}
 
 impl ServiceError {
     pub fn new<M: Into<String>>(message:M) -> ServiceError {
-        ServiceError { message: message.into() }
+        ServiceError::of_kind(ErrorKind::Other, message)
+    }
+
+    pub fn of_kind<M: Into<String>>(kind: ErrorKind, message: M) -> ServiceError {
+        ServiceError { message: message.into(), exit_code: kind.exit_code(), kind, source: None, context: vec![] }
     }
 
     pub fn with<T: Debug>(error: T, message: &str) -> ServiceError {
-        ServiceError { message: format!("{} -> {:?}", message, error), }
+        ServiceError::new(format!("{} -> {:?}", message, error))
+    }
+
+    /// Keeps the error as the source. The kind is the one of the source if it is a
+    /// `ServiceError` (with its exit code) or an I/O error, `Other` otherwise.
+    pub fn caused_by<E: Error + Send + Sync + 'static>(error: E, message: &str) -> ServiceError {
+        let source: Box<dyn Error + Send + Sync + 'static> = Box::new(error);
+        let (kind, exit_code) = match source.downcast_ref::<ServiceError>() {
+            Some(cause) => (cause.kind, cause.exit_code),
+            None if source.is::<io::Error>() => (ErrorKind::Io, ErrorKind::Io.exit_code()),
+            None => (ErrorKind::Other, ErrorKind::Other.exit_code()),
+        };
+        ServiceError { message: String::from(message), exit_code, kind, source: Some(source), context: vec![] }
+    }
+
+    /// Sets the kind, and the exit code of the kind.
+    pub fn with_kind(mut self, kind: ErrorKind) -> ServiceError {
+        self.kind = kind;
+        self.exit_code = kind.exit_code();
+        self
+    }
+
+    /// Sets the exit code of the service if the error stops the service.
+    pub fn with_exit_code(mut self, exit_code: u32) -> ServiceError {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// Adds a key and a value to the context, E.g. the application or the phase.
+    pub fn with_context<V: ToString>(mut self, key: &str, value: V) -> ServiceError {
+        self.context.push((String::from(key), value.to_string()));
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    // Whether `with_exit_code` gives the error another exit code than the one of its kind.
+    pub(crate) fn has_own_exit_code(&self) -> bool {
+        self.exit_code != self.kind.exit_code()
+    }
+
+    /// The value of a key of the context, the last one if the key is given several times.
+    pub fn context(&self, key: &str) -> Option<&str> {
+        self.context.iter().rev().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+
+    /// The errors which cause this one, from the direct cause.
+    pub fn chain(&self) -> impl Iterator<Item = &(dyn Error + 'static)> {
+        std::iter::successors(self.source(), |&error| error.source())
     }
 }
 
 impl fmt::Display for ServiceError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
+        write!(f, "{}", self.message)?;
+        if let Some(source) = &self.source {
+            write!(f, " -> {}", source)?;
+        }
+        if !self.context.is_empty() {
+            let context: Vec<String> = self.context.iter().map(|(key, value)| format!("{}: {}", key, value)).collect();
+            write!(f, " [{}]", context.join(", "))?;
+        }
+        Ok(())
     }
 }
 
 impl Debug for ServiceError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
+        write!(f, "{}", self)
     }
 }
 
-impl std::error::Error for ServiceError {
+impl Error for ServiceError {
     fn description(&self) -> &str {
         &self.message
     }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|source| source.as_ref() as &(dyn Error + 'static))
+    }
+}
+
+impl From<io::Error> for ServiceError {
+    fn from(error: io::Error) -> Self {
+        ServiceError::caused_by(error, "I/O error. ")
+    }
+}
+
+#[cfg(windows)]
+impl From<windows_service::Error> for ServiceError {
+    fn from(error: windows_service::Error) -> Self {
+        ServiceError::caused_by(error, "Service manager error. ").with_kind(ErrorKind::Platform)
+    }
 }
 
-pub type ServiceResult<T> = Result<T, ServiceError>;
\ No newline at end of file
+pub type ServiceResult<T> = Result<T, ServiceError>;
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use crate::exit_code;
+    use super::{ErrorKind, ServiceError};
+
+    #[test]
+    fn kind_gives_the_exit_code() {
+        assert_eq!(ErrorKind::Config.exit_code(), exit_code::CONFIG_ERROR);
+        assert_eq!(ErrorKind::Io.exit_code(), exit_code::IO_ERROR);
+        assert_eq!(ErrorKind::Platform.exit_code(), exit_code::PLATFORM_ERROR);
+        assert_eq!(ErrorKind::Timeout.exit_code(), exit_code::TIMEOUT);
+        assert_eq!(ErrorKind::Cancelled.exit_code(), exit_code::CANCELLED);
+        assert_eq!(ErrorKind::Application.exit_code(), exit_code::FAILURE);
+        assert_eq!(ErrorKind::Other.exit_code(), exit_code::FAILURE);
+
+        let error = ServiceError::caused_by(io::Error::from(io::ErrorKind::NotFound), "Fail to read. ");
+        assert_eq!((error.kind(), error.exit_code), (ErrorKind::Io, exit_code::IO_ERROR));
+        let error = error.with_kind(ErrorKind::Config);
+        assert_eq!((error.kind(), error.exit_code), (ErrorKind::Config, exit_code::CONFIG_ERROR));
+        assert!(!error.has_own_exit_code());
+    }
+
+    #[test]
+    fn keeps_its_own_exit_code_through_the_chain() {
+        let error = ServiceError::of_kind(ErrorKind::Io, "The disk is full. ").with_exit_code(42);
+        assert!(error.has_own_exit_code());
+
+        let wrapping = ServiceError::caused_by(error, "Fail to write. ");
+        assert_eq!((wrapping.kind(), wrapping.exit_code), (ErrorKind::Io, 42));
+    }
+}
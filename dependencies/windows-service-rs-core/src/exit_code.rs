@@ -0,0 +1,45 @@
+use std::convert::TryFrom;
+
+// The exit codes of the service. They are reported to the Windows SCM as service specific exit
+// codes and used as the process exit status on Linux.
+pub const SUCCESS: u32 = 0;
+pub const FAILURE: u32 = 1;
+// Some applications do not exit before the stop deadline and are abandoned.
+pub const STOP_TIMEOUT: u32 = 2;
+// An application fails to initialize, or the applications are not initialized before the start
+// timeout. The service never reports Running.
+pub const START_FAILURE: u32 = 3;
+// A critical application fails, or an application is restarted too often and its escalation is
+// to stop the service. The exit code is APPLICATION_FAILURE plus the index of the application
+// (please refer to `application_failure`), unless the error of the application is given its own
+// exit code with `ServiceError::with_exit_code`. The exit code of the kind of the error doesn't
+// replace it, so that the exit code tells which application fails.
+pub const APPLICATION_FAILURE: u32 = 100;
+// The exit codes of the error kinds, please refer to `ErrorKind::exit_code`. E.g. an invalid
+// configuration stops the service with CONFIG_ERROR, rather than START_FAILURE.
+pub const CONFIG_ERROR: u32 = 4;
+pub const IO_ERROR: u32 = 5;
+pub const PLATFORM_ERROR: u32 = 6;
+pub const TIMEOUT: u32 = 7;
+pub const CANCELLED: u32 = 8;
+// The process exit status is a single byte on Linux.
+const MAX_EXIT_CODE: u32 = 255;
+
+pub fn application_failure(index: usize) -> u32 {
+    APPLICATION_FAILURE.saturating_add(u32::try_from(index).unwrap_or(u32::MAX)).min(MAX_EXIT_CODE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{application_failure, APPLICATION_FAILURE};
+
+    #[test]
+    fn application_failure_is_capped() {
+        assert_eq!(application_failure(0), APPLICATION_FAILURE);
+        assert_eq!(application_failure(7), APPLICATION_FAILURE + 7);
+        assert_eq!(application_failure(155), 255);
+        assert_eq!(application_failure(156), 255);
+        assert_eq!(application_failure(usize::MAX), 255);
+        assert_eq!(application_failure(u32::MAX as usize + 1), 255);
+    }
+}
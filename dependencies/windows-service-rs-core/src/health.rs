@@ -0,0 +1,55 @@
+use std::fmt;
+
+/// The health of an application, from the best to the worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthStatus {
+    Healthy,
+    /// The application works, but not as well as it should (E.g. it falls back to a cache).
+    Degraded,
+    /// The application doesn't work.
+    Unhealthy,
+}
+
+impl HealthStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// The result of a health check. The detail tells what is wrong, E.g. "The database is not
+// reachable.", it is served as is to the monitoring probes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub detail: String,
+}
+
+impl HealthReport {
+    pub fn healthy() -> HealthReport {
+        HealthReport { status: HealthStatus::Healthy, detail: String::new() }
+    }
+
+    pub fn degraded<D: Into<String>>(detail: D) -> HealthReport {
+        HealthReport { status: HealthStatus::Degraded, detail: detail.into() }
+    }
+
+    pub fn unhealthy<D: Into<String>>(detail: D) -> HealthReport {
+        HealthReport { status: HealthStatus::Unhealthy, detail: detail.into() }
+    }
+}
+
+impl Default for HealthReport {
+    fn default() -> Self {
+        HealthReport::healthy()
+    }
+}
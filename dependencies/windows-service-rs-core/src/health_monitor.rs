@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::error::ServiceResult;
+use crate::health::{HealthReport, HealthStatus};
+use crate::host::{HostState, HostStatus, StatusReporter};
+use crate::http_server::HttpResponse;
+use crate::registry::RegisteredApplication;
+use crate::supervisor::ReplicaSet;
+
+// The health monitor polls the health of the running applications on an interval, and serves
+// the results from the local HTTP server:
+//
+// (1) `/healthz` tells whether the service is alive: it answers 200 unless an application is
+//     unhealthy (503). A degraded application is still alive.
+// (2) `/readyz` tells whether the service can take traffic: it answers 200 only if the service
+//     is running (E.g. not starting, paused nor stopping) and no application is unhealthy.
+//
+// Both return the same JSON document, E.g.
+//
+// {"status":"degraded","ready":true,"state":"Running","applications":[
+//     {"index":0,"name":"cache","replicas":1,"status":"degraded","detail":"Falls back to the disk."}]}
+//
+// An application which is not running (E.g. it is being restarted or it has failed) is
+// unhealthy, and so is an application which misses its heartbeat (please refer to the
+// `watchdog` module). The health of an application with several replicas is the worst health
+// of its replicas, E.g. it is unhealthy if one of its replicas is not running. The health is
+// polled rather than checked on each request, so that a slow health check doesn't hold the
+// probes.
+pub(crate) struct HealthMonitor {
+    applications: Vec<MonitoredApplication>,
+    // The number of replicas and the health of each application.
+    reports: Mutex<Vec<(usize, HealthReport)>>,
+    state: Mutex<HostState>,
+}
+
+struct MonitoredApplication {
+    index: usize,
+    application: RegisteredApplication,
+    replicas: ReplicaSet,
+}
+
+impl HealthMonitor {
+    pub(crate) fn new(applications: &[RegisteredApplication], replicas: &[ReplicaSet]) -> HealthMonitor {
+        let applications: Vec<MonitoredApplication> = applications.iter().zip(replicas).enumerate()
+            .map(|(index, (application, replicas))| MonitoredApplication {
+                index,
+                application: application.clone(),
+                replicas: replicas.clone(),
+            })
+            .collect();
+        let reports = vec![(0, HealthReport::degraded("The application is starting.")); applications.len()];
+        HealthMonitor { applications, reports: Mutex::new(reports), state: Mutex::new(HostState::StartPending) }
+    }
+
+    // Wraps the reporter, so that the monitor knows the state of the service.
+    pub(crate) fn track(self: &Arc<Self>, reporter: Arc<dyn StatusReporter>) -> Arc<dyn StatusReporter> {
+        Arc::new(TrackingReporter { reporter, monitor: self.clone() })
+    }
+
+    pub(crate) fn poll(&self) {
+        let starting = self.state() == HostState::StartPending;
+        let reports = self.applications.iter()
+            .map(|monitored| {
+                let replicas = monitored.replicas.snapshot();
+                let heartbeat = monitored.application.options().heartbeat;
+                let count = replicas.len();
+                let report = replicas.into_iter()
+                    .map(|(replica, live)| {
+                        // A hung application can't be trusted to report its health.
+                        let overdue = live.as_ref().and_then(|live| {
+                            heartbeat.and_then(|heartbeat| live.context.heartbeat_clock().overdue(heartbeat.timeout))
+                        });
+                        let report = match (live, overdue) {
+                            (Some(_), Some(overdue)) => HealthReport::unhealthy(format!("No heartbeat for {:?}.", overdue)),
+                            (Some(live), None) => live.instance.health(&monitored.application.replica_identity(monitored.index, replica)),
+                            (None, _) if starting => HealthReport::degraded("The application is starting."),
+                            (None, _) => HealthReport::unhealthy("The application is not running."),
+                        };
+                        if count == 1 || report.detail.is_empty() {
+                            return report;
+                        }
+                        HealthReport { detail: format!("Replica {}: {}", replica, report.detail), ..report }
+                    })
+                    // The first of the worst reports.
+                    .fold(None, |worst: Option<HealthReport>, report| match worst {
+                        Some(worst) if worst.status >= report.status => Some(worst),
+                        _ => Some(report),
+                    })
+                    .unwrap_or_else(|| { HealthReport::unhealthy("The application has no replica.") });
+                (count, report)
+            })
+            .collect();
+        self.reports.lock().map(|mut current| { *current = reports; }).unwrap_or_default();
+    }
+
+    fn state(&self) -> HostState {
+        self.state.lock().map(|state| *state).unwrap_or(HostState::StartPending)
+    }
+
+    // Returns None if the path is not a health endpoint.
+    pub(crate) fn respond(&self, path: &str) -> Option<HttpResponse> {
+        let state = self.state();
+        let reports = self.reports.lock().map(|reports| reports.clone()).unwrap_or_default();
+        let status = reports.iter().map(|(_, report)| report.status).max().unwrap_or(HealthStatus::Healthy);
+        let ready = state == HostState::Running && status != HealthStatus::Unhealthy;
+        let ok = match path {
+            "/healthz" => status != HealthStatus::Unhealthy,
+            "/readyz" => ready,
+            _ => return None,
+        };
+
+        let applications: Vec<Value> = self.applications.iter().zip(&reports).enumerate()
+            .map(|(index, (monitored, (replicas, report)))| json!({
+                "index": index,
+                "name": monitored.application.name(),
+                "replicas": replicas,
+                "status": report.status.as_str(),
+                "detail": report.detail,
+            }))
+            .collect();
+        let body = json!({
+            "status": status.as_str(),
+            "ready": ready,
+            "state": format!("{:?}", state),
+            "applications": applications,
+        });
+        Some(HttpResponse::json(if ok { 200 } else { 503 }, body.to_string()))
+    }
+}
+
+struct TrackingReporter {
+    reporter: Arc<dyn StatusReporter>,
+    monitor: Arc<HealthMonitor>,
+}
+
+impl StatusReporter for TrackingReporter {
+    fn report(&self, status: HostStatus) -> ServiceResult<()> {
+        self.monitor.state.lock().map(|mut state| { *state = status.state; }).unwrap_or_default();
+        self.reporter.report(status)
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        self.reporter.watchdog_interval()
+    }
+
+    fn keep_alive(&self) -> ServiceResult<()> {
+        self.reporter.keep_alive()
+    }
+}
+
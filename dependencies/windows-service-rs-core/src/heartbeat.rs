@@ -0,0 +1,82 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// What the host does when an application misses its heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedHeartbeat {
+    /// Logs it. The application is unhealthy until it sends a heartbeat again.
+    Report,
+    /// Requests the instance to exit, and restarts it according to the restart policy, like a
+    /// failure. A deadlocked thread can't be interrupted though: if the instance doesn't exit,
+    /// it stays hung.
+    Restart,
+    /// Stops the service, with `exit_code::TIMEOUT`.
+    StopService,
+}
+
+/// The heartbeat an application promises to send while it runs, please refer to
+/// `ApplicationOptions::heartbeat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatPolicy {
+    /// The application is hung if it doesn't send a heartbeat for this long.
+    pub timeout: Duration,
+    pub on_missed: MissedHeartbeat,
+}
+
+// The heartbeat of a replica, shared by its run context and the host. The host starts the clock
+// when an instance starts running and stops it when the instance exits, so that an application
+// which is initializing or being restarted doesn't miss its heartbeat. A paused application is
+// not expected to send heartbeats either, the watchdog keeps its clock running.
+#[derive(Clone, Default)]
+pub(crate) struct Heartbeat {
+    shared: Arc<Mutex<HeartbeatState>>,
+}
+
+#[derive(Default)]
+struct HeartbeatState {
+    // The last heartbeat (or the start of the instance), None if the clock is stopped.
+    last: Option<Instant>,
+    // The watchdog has reported the missed heartbeat.
+    missed: bool,
+    // The watchdog has requested the instance to exit, its exit is a failure.
+    hung: bool,
+}
+
+impl Heartbeat {
+    pub(crate) fn beat(&self) {
+        let mut state = self.lock();
+        if state.last.is_some() {
+            state.last = Some(Instant::now());
+        }
+    }
+
+    pub(crate) fn start(&self) {
+        *self.lock() = HeartbeatState { last: Some(Instant::now()), missed: false, hung: false };
+    }
+
+    // Returns whether the watchdog has requested the instance to exit.
+    pub(crate) fn stop(&self) -> bool {
+        let mut state = self.lock();
+        state.last = None;
+        state.missed = false;
+        std::mem::replace(&mut state.hung, false)
+    }
+
+    // The time since the last heartbeat, if it is longer than the timeout.
+    pub(crate) fn overdue(&self, timeout: Duration) -> Option<Duration> {
+        self.lock().last.map(|last| last.elapsed()).filter(|elapsed| *elapsed > timeout)
+    }
+
+    // Records whether the heartbeat is missed, and returns whether it has changed.
+    pub(crate) fn set_missed(&self, missed: bool) -> bool {
+        std::mem::replace(&mut self.lock().missed, missed) != missed
+    }
+
+    pub(crate) fn set_hung(&self) {
+        self.lock().hung = true;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HeartbeatState> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,156 @@
+use std::env;
+use std::ffi::OsString;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::service_config::ServiceConfig;
+use crate::host::{self, HostBackend, HostControl, HostSession, HostStatus, ServiceMain, StatusReporter};
+
+// The console backend runs the service in the foreground, just like a plain command line
+// application. It is used to run and debug the service host locally (or in integration tests)
+// without installing it to a service manager. Ctrl+C (SIGINT) and SIGTERM are translated to
+// `HostControl::Stop` and the state changes are printed to the console. On unix, Ctrl+Z (SIGTSTP)
+// and SIGCONT pause and continue the service, SIGHUP reloads the configuration, and the signals
+// of `ServiceConfig::user_control_signals` send user-defined commands.
+pub struct ConsoleBackend {}
+
+impl ConsoleBackend {
+    pub fn new() -> ConsoleBackend {
+        ConsoleBackend {}
+    }
+}
+
+impl Default for ConsoleBackend {
+    fn default() -> Self {
+        ConsoleBackend::new()
+    }
+}
+
+impl HostBackend for ConsoleBackend {
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn run(&self, config: &ServiceConfig, service_main: ServiceMain) -> ServiceResult<()> {
+        let (control_sender, control_receiver) = mpsc::channel();
+        let stop_handler = StopHandler::install(config, control_sender)?;
+
+        println!("Running {} in console mode, press Ctrl+C to stop...", config.service_name);
+        let result = service_main(HostSession {
+            arguments: env::args_os().skip(1)
+                .filter(|argument| argument != host::CONSOLE_ARGUMENT)
+                .collect::<Vec<OsString>>(),
+            reporter: Arc::new(ConsoleReporter { service_name: config.service_name.clone() }),
+            controls: control_receiver,
+        });
+
+        stop_handler.uninstall();
+        result
+    }
+}
+
+struct ConsoleReporter {
+    service_name: String,
+}
+
+impl StatusReporter for ConsoleReporter {
+    fn report(&self, status: HostStatus) -> ServiceResult<()> {
+        log::info!("Setting service status for {}: {:?}.", self.service_name, status);
+        println!("Service {} state: {:?}", self.service_name, status.state);
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+struct StopHandler {
+    signals_handle: signal_hook::iterator::Handle,
+    signal_thread: std::thread::JoinHandle<()>,
+}
+
+#[cfg(unix)]
+impl StopHandler {
+    fn install(config: &ServiceConfig, control_sender: Sender<HostControl>) -> ServiceResult<StopHandler> {
+        use signal_hook::consts::{SIGCONT, SIGHUP, SIGINT, SIGTERM, SIGTSTP};
+        use signal_hook::iterator::Signals;
+
+        let built_in = [SIGINT, SIGTERM, SIGTSTP, SIGCONT, SIGHUP];
+        let mut signals = Signals::new(host::signals_with_user_controls(config, &built_in))
+            .map_err(|e| { ServiceError::caused_by(e, "Fail to register signal handler. ").with_kind(ErrorKind::Platform) })?;
+        let signals_handle = signals.handle();
+        let config = config.clone();
+        let signal_thread = std::thread::spawn(move || {
+            for signal in signals.forever() {
+                let control = match signal {
+                    SIGINT | SIGTERM => HostControl::Stop,
+                    SIGTSTP => HostControl::Pause,
+                    SIGCONT => HostControl::Continue,
+                    SIGHUP => HostControl::ParamChange,
+                    _ => match host::user_control(&config, signal) {
+                        Some(control) => control,
+                        None => continue,
+                    },
+                };
+                println!("Signal {} received: {:?}", signal, control);
+                control_sender.send(control).unwrap_or_default();
+            }
+        });
+
+        Ok(StopHandler { signals_handle, signal_thread })
+    }
+
+    fn uninstall(self) {
+        self.signals_handle.close();
+        self.signal_thread.join().unwrap_or_else(|e| {
+            log::error!("Signal thread error: {:?}", e);
+        });
+    }
+}
+
+// The console control handler is a plain function, so the control sender is handed over to it
+// through this slot.
+#[cfg(windows)]
+static CONSOLE_CONTROLS: std::sync::Mutex<Option<Sender<HostControl>>> = std::sync::Mutex::new(None);
+
+#[cfg(windows)]
+struct StopHandler {}
+
+#[cfg(windows)]
+impl StopHandler {
+    fn install(_config: &ServiceConfig, control_sender: Sender<HostControl>) -> ServiceResult<StopHandler> {
+        use winapi::shared::minwindef::TRUE;
+
+        *CONSOLE_CONTROLS.lock().map_err(|e| { ServiceError::with(e, "Fail to store console controls. ").with_kind(ErrorKind::Platform) })? =
+            Some(control_sender);
+        if unsafe { winapi::um::consoleapi::SetConsoleCtrlHandler(Some(console_ctrl_handler), TRUE) } == 0 {
+            return Err(ServiceError::caused_by(std::io::Error::last_os_error(), "Fail to register console control handler. ")
+                .with_kind(ErrorKind::Platform));
+        }
+        Ok(StopHandler {})
+    }
+
+    fn uninstall(self) {
+        use winapi::shared::minwindef::FALSE;
+
+        unsafe { winapi::um::consoleapi::SetConsoleCtrlHandler(Some(console_ctrl_handler), FALSE); }
+        CONSOLE_CONTROLS.lock().map(|mut controls| { controls.take(); }).unwrap_or_default();
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn console_ctrl_handler(ctrl_type: winapi::shared::minwindef::DWORD) -> winapi::shared::minwindef::BOOL {
+    use winapi::shared::minwindef::{FALSE, TRUE};
+    use winapi::um::wincon::{CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT, CTRL_SHUTDOWN_EVENT};
+
+    let control = match ctrl_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT | CTRL_CLOSE_EVENT => HostControl::Stop,
+        CTRL_SHUTDOWN_EVENT => HostControl::Shutdown,
+        _ => return FALSE,
+    };
+    println!("Console control event {} received, stopping...", ctrl_type);
+    if let Ok(controls) = CONSOLE_CONTROLS.lock() {
+        if let Some(sender) = controls.as_ref() {
+            sender.send(control).unwrap_or_default();
+        }
+    }
+    TRUE
+}
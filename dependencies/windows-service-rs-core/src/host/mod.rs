@@ -0,0 +1,197 @@
+use std::env;
+use std::ffi::OsString;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use crate::error::ServiceResult;
+use crate::exit_code;
+use crate::service_config::ServiceConfig;
+
+pub mod console;
+#[cfg(windows)]
+pub mod windows_scm;
+#[cfg(unix)]
+pub mod systemd;
+
+// The host backend is the bridge between the service manager of the platform (the Windows
+// service control manager, systemd, ...) and the platform independent service logic in
+// `service_wrapper`. A backend has three responsibilities:
+//
+// (1) Enter the service manager and invoke the service main function when the service
+//     manager is ready.
+// (2) Translate the control requests of the service manager (Windows controls, signals) into
+//     `HostControl` values and send them to the service main function.
+// (3) Report the state changes of the service back to the service manager.
+
+/// The state of the service as it is reported to the service manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostState {
+    StartPending,
+    Running,
+    StopPending,
+    Stopped,
+    PausePending,
+    Paused,
+    ContinuePending,
+}
+
+/// A status report sent to the service manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostStatus {
+    pub state: HostState,
+    /// Increased periodically while a pending operation makes progress.
+    pub checkpoint: u32,
+    /// How long the service manager should wait for the next report of a pending state.
+    pub wait_hint: Duration,
+    /// The exit code of the service, only meaningful in the `Stopped` state. Please refer to
+    /// the `exit_code` module.
+    pub exit_code: u32,
+}
+
+impl HostStatus {
+    pub fn new(state: HostState) -> HostStatus {
+        HostStatus { state, checkpoint: 0, wait_hint: Duration::default(), exit_code: exit_code::SUCCESS }
+    }
+
+    pub fn with_checkpoint(mut self, checkpoint: u32) -> HostStatus {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    pub fn with_exit_code(mut self, exit_code: u32) -> HostStatus {
+        self.exit_code = exit_code;
+        self
+    }
+
+    pub fn with_wait_hint(mut self, wait_hint: Duration) -> HostStatus {
+        self.wait_hint = wait_hint;
+        self
+    }
+}
+
+/// The platform independent control requests sent by the service manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostControl {
+    Stop,
+    Pause,
+    Continue,
+    /// The machine is shutting down.
+    Shutdown,
+    /// The machine is about to shut down. Only the Windows SCM sends it, and it gives the
+    /// service more time than `Shutdown` to stop.
+    Preshutdown,
+    /// The configuration of the service has changed. The Windows SCM sends it (E.g.
+    /// `sc control <service> paramchange`), and SIGHUP is translated to it on unix.
+    ParamChange,
+    /// A user-defined control code, 128 to 255. The Windows SCM sends it (E.g. `sc control
+    /// <service> 130`), and the signals mapped by `ServiceConfig::user_control_signals` are
+    /// translated to it on unix. Please refer to the `command` module.
+    User(u8),
+}
+
+// The signals a unix backend registers: its own signals, and the signals mapped to user-defined
+// control codes. A mapped signal the backend handles already is left out, so that a mapping
+// can't take the stop signal away.
+#[cfg(unix)]
+pub(crate) fn signals_with_user_controls(config: &ServiceConfig, built_in: &[i32]) -> Vec<i32> {
+    let mut signals = built_in.to_vec();
+    for (signal, code) in config.user_control_signals.iter() {
+        if built_in.contains(signal) {
+            log::warn!("Signal {} is handled by the host, it can't send control code {}.", signal, code);
+        } else if !signals.contains(signal) {
+            signals.push(*signal);
+        }
+    }
+    signals
+}
+
+#[cfg(unix)]
+pub(crate) fn user_control(config: &ServiceConfig, signal: i32) -> Option<HostControl> {
+    config.user_control_signals.iter()
+        .find(|(mapped, _)| *mapped == signal)
+        .map(|(_, code)| HostControl::User(*code))
+}
+
+pub trait StatusReporter: Send + Sync {
+    fn report(&self, status: HostStatus) -> ServiceResult<()>;
+
+    /// How often the service manager expects a keep-alive, if it watches the service for hangs
+    /// (E.g. `WatchdogSec` of a systemd unit). None by default.
+    fn watchdog_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Tells the service manager that the service is alive. Please refer to the `watchdog`
+    /// module.
+    fn keep_alive(&self) -> ServiceResult<()> {
+        Ok(())
+    }
+}
+
+/// Everything the service main function gets from the backend.
+pub struct HostSession {
+    pub arguments: Vec<OsString>,
+    pub reporter: Arc<dyn StatusReporter>,
+    pub controls: Receiver<HostControl>,
+}
+
+pub type ServiceMain = Box<dyn FnOnce(HostSession) -> ServiceResult<()> + Send>;
+
+pub trait HostBackend {
+    fn name(&self) -> &str;
+    fn run(&self, config: &ServiceConfig, service_main: ServiceMain) -> ServiceResult<()>;
+}
+
+/// Forces the console backend when passed as a start argument.
+pub const CONSOLE_ARGUMENT: &str = "--console";
+/// Forces the console backend (`console`) or the service manager backend (`service`).
+pub const RUN_MODE_ENVIRONMENT_VARIABLE: &str = "SERVICE_RUN_MODE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    /// Run under the service manager of the platform.
+    Service,
+    /// Run in the foreground, see `console::ConsoleBackend`.
+    Console,
+    /// Run under the service manager if the process is launched by it, otherwise run in the
+    /// foreground.
+    Auto,
+}
+
+pub fn run_mode_from_environment() -> RunMode {
+    if env::args_os().skip(1).any(|argument| argument == CONSOLE_ARGUMENT) {
+        return RunMode::Console;
+    }
+
+    match env::var(RUN_MODE_ENVIRONMENT_VARIABLE).map(|mode| mode.to_lowercase()) {
+        Ok(mode) if mode == "console" => RunMode::Console,
+        Ok(mode) if mode == "service" => RunMode::Service,
+        _ => RunMode::Auto,
+    }
+}
+
+pub fn default_backend() -> Box<dyn HostBackend> {
+    backend_for(run_mode_from_environment())
+}
+
+#[cfg(windows)]
+pub fn backend_for(run_mode: RunMode) -> Box<dyn HostBackend> {
+    // There is no reliable way to know whether the process is launched by the service control
+    // manager before calling the service dispatcher. So in auto mode, the SCM backend falls back
+    // to the console backend when the dispatcher fails to connect to the SCM.
+    match run_mode {
+        RunMode::Service => Box::new(windows_scm::WindowsScmBackend::new()),
+        RunMode::Console => Box::new(console::ConsoleBackend::new()),
+        RunMode::Auto => Box::new(windows_scm::WindowsScmBackend::new().with_console_fallback(true)),
+    }
+}
+
+#[cfg(unix)]
+pub fn backend_for(run_mode: RunMode) -> Box<dyn HostBackend> {
+    match run_mode {
+        RunMode::Service => Box::new(systemd::SystemdBackend::new()),
+        RunMode::Console => Box::new(console::ConsoleBackend::new()),
+        RunMode::Auto if systemd::is_launched_by_systemd() => Box::new(systemd::SystemdBackend::new()),
+        RunMode::Auto => Box::new(console::ConsoleBackend::new()),
+    }
+}
@@ -0,0 +1,305 @@
+use std::env;
+use std::ffi::OsString;
+use std::io::Read;
+use std::process::{self, Command, Stdio};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use signal_hook::consts::{SIGCONT, SIGHUP, SIGTERM, SIGTSTP};
+use signal_hook::iterator::Signals;
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::exit_code;
+use crate::service_config::ServiceConfig;
+use crate::host::{self, HostBackend, HostControl, HostSession, HostState, HostStatus, ServiceMain, StatusReporter};
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+// The watchdog of the unit (`WatchdogSec`), in microseconds, and the process it watches.
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
+// systemd sets a unique invocation id for every unit it starts.
+const INVOCATION_ID: &str = "INVOCATION_ID";
+// How long a SIGTERM waits for the system state, please refer to `is_system_stopping`.
+const SYSTEM_STATE_TIMEOUT: Duration = Duration::from_millis(500);
+const SYSTEM_STATE_POLLING_INTERVAL: Duration = Duration::from_millis(10);
+
+pub fn is_launched_by_systemd() -> bool {
+    env::var_os(NOTIFY_SOCKET).is_some() || env::var_os(INVOCATION_ID).is_some()
+}
+
+// The systemd backend runs the service main function directly on the calling thread. The state
+// changes are reported through the sd_notify protocol: a datagram containing newline separated
+// `KEY=VALUE` assignments is sent to the unix socket passed in the NOTIFY_SOCKET environment
+// variable. This only works for units declared with `Type=notify`, for other units (or when the
+// process is not started by systemd) the notifications are silently skipped.
+//
+// systemd stops a service by sending SIGTERM, which is translated to `HostControl::Stop`, or to
+// `HostControl::Shutdown` if the whole system is going down. Either way the service has to exit
+// within the TimeoutStopSec of the unit (unless the timeout is extended by the wait hints). There is
+// no pause/continue in systemd, so SIGTSTP and SIGCONT are translated to `HostControl::Pause` and
+// `HostControl::Continue` (E.g. `systemctl kill --signal=SIGTSTP <unit>`). SIGHUP is translated
+// to `HostControl::ParamChange`, so `ExecReload=/bin/kill -HUP $MAINPID` makes
+// `systemctl reload <unit>` reload the configuration. The signals of
+// `ServiceConfig::user_control_signals` are translated to `HostControl::User`, E.g.
+// `systemctl kill --signal=SIGUSR1 <unit>` sends a user-defined command.
+//
+// If the unit has `WatchdogSec`, systemd passes it in WATCHDOG_USEC and expects `WATCHDOG=1`
+// at least that often, otherwise it kills the service (and restarts it with `Restart=`). The
+// host sends it only while every application sends its heartbeat, please refer to the
+// `watchdog` module.
+pub struct SystemdBackend {}
+
+impl SystemdBackend {
+    pub fn new() -> SystemdBackend {
+        SystemdBackend {}
+    }
+}
+
+impl Default for SystemdBackend {
+    fn default() -> Self {
+        SystemdBackend::new()
+    }
+}
+
+impl HostBackend for SystemdBackend {
+    fn name(&self) -> &str {
+        "systemd"
+    }
+
+    fn run(&self, config: &ServiceConfig, service_main: ServiceMain) -> ServiceResult<()> {
+        let (control_sender, control_receiver) = mpsc::channel();
+
+        let built_in = [SIGTERM, SIGTSTP, SIGCONT, SIGHUP];
+        let mut signals = Signals::new(host::signals_with_user_controls(config, &built_in))
+            .map_err(|e| { ServiceError::caused_by(e, "Fail to register signal handler. ").with_kind(ErrorKind::Platform) })?;
+        let signals_handle = signals.handle();
+        let signal_config = config.clone();
+        let signal_thread = thread::spawn(move || {
+            for signal in signals.forever() {
+                log::info!("Signal received: {}.", signal);
+                let control = match signal {
+                    SIGTERM if is_system_stopping() => HostControl::Shutdown,
+                    SIGTERM => HostControl::Stop,
+                    SIGTSTP => HostControl::Pause,
+                    SIGCONT => HostControl::Continue,
+                    SIGHUP => HostControl::ParamChange,
+                    _ => match host::user_control(&signal_config, signal) {
+                        Some(control) => control,
+                        None => continue,
+                    },
+                };
+                control_sender.send(control).unwrap_or_default();
+            }
+        });
+
+        let result = service_main(HostSession {
+            arguments: env::args_os().skip(1).collect::<Vec<OsString>>(),
+            reporter: Arc::new(SdStatusReporter {
+                notifier: SdNotifier::from_environment(),
+                config: config.clone(),
+                watchdog_interval: watchdog_interval_from_environment(),
+            }),
+            controls: control_receiver,
+        });
+
+        signals_handle.close();
+        signal_thread.join().unwrap_or_else(|e| {
+            log::error!("Signal thread error: {:?}", e);
+        });
+        result
+    }
+}
+
+// systemd doesn't tell the service why it is stopped. The system state is "stopping" while the
+// system is shutting down (or rebooting). systemd is busy then, so `systemctl` may be slow to
+// answer: it is given SYSTEM_STATE_TIMEOUT, afterwards the service is just stopped, rather than
+// eating up the TimeoutStopSec of the unit.
+fn is_system_stopping() -> bool {
+    let mut child = match Command::new("systemctl").arg("is-system-running")
+        .stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::null())
+        .spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            log::warn!("Fail to read the system state: {}.", e);
+            return false;
+        },
+    };
+
+    let deadline = Instant::now() + SYSTEM_STATE_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => thread::sleep(SYSTEM_STATE_POLLING_INTERVAL),
+            _ => {
+                log::warn!("The system state is not read in {:?}, the service is stopped.", SYSTEM_STATE_TIMEOUT);
+                child.kill().unwrap_or_default();
+                child.wait().map(|_| ()).unwrap_or_default();
+                return false;
+            },
+        }
+    }
+
+    let mut state = String::new();
+    child.stdout.take()
+        .map(|mut stdout| stdout.read_to_string(&mut state).map(|_| ()).unwrap_or_default())
+        .unwrap_or_default();
+    state.trim() == "stopping"
+}
+
+// The watchdog interval of the unit, if the watchdog is enabled for this process.
+fn watchdog_interval_from_environment() -> Option<Duration> {
+    let pid_matches = env::var(WATCHDOG_PID).ok()
+        .map(|pid| { pid.trim().parse::<u32>().map(|pid| pid == process::id()).unwrap_or(false) })
+        .unwrap_or(true);
+    env::var(WATCHDOG_USEC).ok()
+        .and_then(|usec| { usec.trim().parse::<u64>().ok() })
+        .filter(|usec| *usec > 0 && pid_matches)
+        .map(Duration::from_micros)
+}
+
+pub struct SdNotifier {
+    socket_path: Option<OsString>,
+}
+
+impl SdNotifier {
+    pub fn from_environment() -> SdNotifier {
+        SdNotifier { socket_path: env::var_os(NOTIFY_SOCKET).filter(|path| !path.is_empty()) }
+    }
+
+    pub fn notify(&self, message: &str) -> ServiceResult<()> {
+        let socket_path = match &self.socket_path {
+            None => return Ok(()),
+            Some(socket_path) => socket_path,
+        };
+
+        log::debug!("Sending notification to systemd: {:?}.", message);
+        let socket = UnixDatagram::unbound()
+            .map_err(|e| { ServiceError::caused_by(e, "Fail to create notification socket. ").with_kind(ErrorKind::Platform) })?;
+        send_notification(&socket, socket_path, message.as_bytes())
+            .map_err(|e| { ServiceError::caused_by(e, "Fail to send notification to systemd. ").with_kind(ErrorKind::Platform) })
+    }
+}
+
+// A socket path starting with '@' refers to the abstract socket namespace of Linux.
+#[cfg(target_os = "linux")]
+fn send_notification(socket: &UnixDatagram, socket_path: &OsString, message: &[u8]) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let path_bytes = socket_path.as_bytes();
+    if path_bytes.first() == Some(&b'@') {
+        let address = SocketAddr::from_abstract_name(&path_bytes[1..])?;
+        socket.send_to_addr(message, &address).map(|_| ())
+    } else {
+        socket.send_to(message, socket_path).map(|_| ())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_notification(socket: &UnixDatagram, socket_path: &OsString, message: &[u8]) -> std::io::Result<()> {
+    socket.send_to(message, socket_path).map(|_| ())
+}
+
+struct SdStatusReporter {
+    notifier: SdNotifier,
+    config: ServiceConfig,
+    watchdog_interval: Option<Duration>,
+}
+
+impl StatusReporter for SdStatusReporter {
+    fn report(&self, status: HostStatus) -> ServiceResult<()> {
+        log::info!("Setting service status for {}: {:?}.", self.config.service_name, status);
+        let mut message = String::from(match status.state {
+            HostState::StartPending => "STATUS=Starting",
+            HostState::Running => "READY=1\nSTATUS=Running",
+            HostState::StopPending => "STOPPING=1\nSTATUS=Stopping",
+            HostState::Stopped => "STATUS=Stopped",
+            HostState::PausePending => "STATUS=Pausing",
+            HostState::Paused => "STATUS=Paused",
+            HostState::ContinuePending => "STATUS=Continuing",
+        });
+        if status.exit_code != exit_code::SUCCESS {
+            message.push_str(&format!(" (exit code {})", status.exit_code));
+        }
+        // The wait hints extend the start and stop timeout of the unit (TimeoutStartSec and
+        // TimeoutStopSec), which is the counterpart of the wait hint of the Windows SCM.
+        if status.wait_hint.as_micros() > 0 {
+            message.push_str(&format!("\nEXTEND_TIMEOUT_USEC={}", status.wait_hint.as_micros()));
+        }
+        self.notifier.notify(&message)
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    fn keep_alive(&self) -> ServiceResult<()> {
+        self.notifier.notify("WATCHDOG=1")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::ffi::OsString;
+    use std::fs;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::Duration;
+    use crate::host::{HostState, HostStatus, StatusReporter};
+    use crate::service_config::ServiceConfig;
+    use super::{SdNotifier, SdStatusReporter};
+
+    // The socket systemd would pass in NOTIFY_SOCKET, and a reporter which sends to it.
+    fn notify_socket(name: &str) -> (UnixDatagram, SdStatusReporter, PathBuf) {
+        let path = env::temp_dir().join(format!("sd-notify-{}-{}.sock", name, process::id()));
+        fs::remove_file(&path).unwrap_or_default();
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let reporter = SdStatusReporter {
+            notifier: SdNotifier { socket_path: Some(OsString::from(&path)) },
+            config: ServiceConfig::default(),
+            watchdog_interval: None,
+        };
+        (socket, reporter, path)
+    }
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 1024];
+        let length = socket.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..length]).into_owned()
+    }
+
+    #[test]
+    fn reports_the_states() {
+        let (socket, reporter, path) = notify_socket("states");
+
+        reporter.report(HostStatus::new(HostState::StartPending).with_wait_hint(Duration::from_secs(10))).unwrap();
+        assert_eq!(receive(&socket), "STATUS=Starting\nEXTEND_TIMEOUT_USEC=10000000");
+        reporter.report(HostStatus::new(HostState::Running)).unwrap();
+        assert_eq!(receive(&socket), "READY=1\nSTATUS=Running");
+        reporter.report(HostStatus::new(HostState::StopPending)).unwrap();
+        assert_eq!(receive(&socket), "STOPPING=1\nSTATUS=Stopping");
+        reporter.report(HostStatus::new(HostState::Stopped).with_exit_code(4)).unwrap();
+        assert_eq!(receive(&socket), "STATUS=Stopped (exit code 4)");
+        fs::remove_file(path).unwrap_or_default();
+    }
+
+    #[test]
+    fn sends_keep_alives() {
+        let (socket, reporter, path) = notify_socket("watchdog");
+
+        reporter.keep_alive().unwrap();
+        assert_eq!(receive(&socket), "WATCHDOG=1");
+        fs::remove_file(path).unwrap_or_default();
+    }
+
+    #[test]
+    fn skips_the_notifications_without_socket() {
+        let notifier = SdNotifier { socket_path: None };
+        notifier.notify("READY=1").unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+use std::ffi::OsString;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult},
+    service_dispatcher
+};
+use windows_service::service_control_handler::ServiceStatusHandle;
+use crate::error::{ErrorKind, ServiceResult, ServiceError};
+use crate::exit_code;
+use crate::host::console::ConsoleBackend;
+use crate::service_config::{AcceptedControls, ServiceConfig, ServiceType};
+use crate::host::{HostBackend, HostControl, HostSession, HostState, HostStatus, ServiceMain, StatusReporter};
+
+// The error code returned by the service dispatcher if the process is not started by the SCM.
+const ERROR_FAILED_SERVICE_CONTROLLER_CONNECT: i32 = 1063;
+
+// The service dispatcher only accepts a plain function as the service entry point, so the
+// service main function (and the service configuration) is handed over to `windows_service_main`
+// through this slot. It is a one-shot hand-off rather than a registry: the slot is filled right
+// before calling the dispatcher and emptied by the service main function.
+static SERVICE_MAIN: Mutex<Option<(ServiceConfig, ServiceMain)>> = Mutex::new(None);
+
+pub struct WindowsScmBackend {
+    console_fallback: bool,
+}
+
+impl WindowsScmBackend {
+    pub fn new() -> WindowsScmBackend {
+        WindowsScmBackend { console_fallback: false }
+    }
+
+    /// Runs the service with the console backend if the process is not started by the SCM.
+    pub fn with_console_fallback(mut self, console_fallback: bool) -> WindowsScmBackend {
+        self.console_fallback = console_fallback;
+        self
+    }
+}
+
+impl Default for WindowsScmBackend {
+    fn default() -> Self {
+        WindowsScmBackend::new()
+    }
+}
+
+impl HostBackend for WindowsScmBackend {
+    fn name(&self) -> &str {
+        "Windows service control manager"
+    }
+
+    fn run(&self, config: &ServiceConfig, service_main: ServiceMain) -> ServiceResult<()> {
+        // The service_dispatcher::start() function does the same thing in a typical window
+        // service. That is:
+        // (1) register service entry point to the service table
+        // (2) call the service dispatcher to invoke the main function of the service.
+        //
+        // C++ equivalent
+        // ------------------------------------------------------------------------------
+        // SERVICE_TABLE_ENTRY ServiceTable[] =
+        // {
+        //   {SERVICE_NAME, (LPSERVICE_MAIN_FUNCTION) ffi_service_main},
+        //   {NULL, NULL}
+        // };
+        //
+        // if (StartServiceCtrlDispatcher (ServiceTable) == FALSE)
+        // {
+        //   return GetLastError ();
+        // }
+        //
+        // return 0;
+        // ------------------------------------------------------------------------------
+        *SERVICE_MAIN.lock().map_err(|e| { ServiceError::with(e, "Fail to store service main. ").with_kind(ErrorKind::Platform) })? =
+            Some((config.clone(), service_main));
+        match service_dispatcher::start(&config.service_name, ffi_service_main) {
+            Err(windows_service::Error::Winapi(ref e))
+                if self.console_fallback && e.raw_os_error() == Some(ERROR_FAILED_SERVICE_CONTROLLER_CONNECT) => {
+                log::info!("The process is not started by the service control manager. Run in console mode.");
+                let (config, service_main) = take_service_main()?;
+                ConsoleBackend::new().run(&config, service_main)
+            },
+            result => {
+                // The slot is taken by the service main function unless the dispatcher failed.
+                // Clear it so that the service can be run again.
+                if result.is_err() {
+                    take_service_main().map(|_| {}).unwrap_or_default();
+                }
+                result.map_err(|e| { ServiceError::caused_by(e, "Fail to call service dispatcher. ").with_kind(ErrorKind::Platform) })
+            },
+        }
+    }
+}
+
+// The macro here is used to handle common argument processing logic for us. It defines a
+// function in the first argument (in this case `ffi_service_main`). In that function it
+// parse the starting arguments and then passes the arguments to the the function defined
+// by the second argument.
+//
+// So the expanded form is something like:
+//
+// fn ffi_service_main(num_service_arguments:u32, service_arguments: **u16) {
+//   let arguments = parse_service_arguments(num_service_arguments, service_arguments);
+//   windows_service_main(arguments);
+// }
+define_windows_service!(ffi_service_main, windows_service_main);
+
+fn windows_service_main(arguments: Vec<OsString>) {
+    // The windows_service_main is called by ffi_service_main. The ffi_service_main follows the
+    // definition LPSERVICE_MAIN_FUNCTION:
+    //
+    // void LpserviceMainFunction(
+    //   DWORD dwNumServicesArgs,
+    //   LPTSTR *lpServiceArgVectors
+    // )
+    //
+    // Thus it will not return any state to the environment. The service just stopped if the
+    // function returns. So if you want to record error message. You would better record in
+    // windows event logs or in the customized log file.
+    run_service_main(arguments).unwrap_or_else(|e| { log::error!("{}", e) });
+}
+
+fn take_service_main() -> ServiceResult<(ServiceConfig, ServiceMain)> {
+    SERVICE_MAIN.lock()
+        .map_err(|e| { ServiceError::with(e, "Fail to get service main. ").with_kind(ErrorKind::Platform) })?
+        .take()
+        .ok_or_else(|| { ServiceError::of_kind(ErrorKind::Platform, "The service main is not available. ") })
+}
+
+fn run_service_main(arguments: Vec<OsString>) -> ServiceResult<()> {
+    let (config, service_main) = take_service_main()?;
+
+    // Since the status callback is an async callback. We have to had a sync mechanism to do the
+    // communication. Just like a message queue. So we create a channel to send the control
+    // requests to the service main function.
+    let (control_sender, control_receiver) = mpsc::channel();
+
+    // To register the callback, we need to declare the callback first. The callback accepts the
+    // desired service status (defined in service::ServiceControl) and returns the
+    // service_control_handler::ServiceControlHandlerResult.
+    //
+    // C++ equivalent
+    // ------------------------------------------------------------------------------
+    // VOID WINAPI EventHandler(DWORD ctrlEvent)
+    // {
+    //   switch (ctrlEvent)
+    //   {
+    //   case SERVICE_CONTROL_STOP :
+    //     if (g_ServiceStatus.dwCurrentState != SERVICE_RUNNING)
+    //       break;
+    //
+    //     g_ServiceStatus.dwControlsAccepted = 0;
+    //     g_ServiceStatus.dwCurrentState = SERVICE_STOP_PENDING;
+    //     g_ServiceStatus.dwWin32ExitCode = 0;
+    //     g_ServiceStatus.dwCheckPoint = 4;
+    //
+    //     if (SetServiceStatus (g_StatusHandle, &g_ServiceStatus) == FALSE)
+    //     {
+    //       // Log something here.
+    //     }
+    //
+    //     // Stop the service anyway.
+    //     SetEvent (g_ServiceStopEvent);
+    //     break;
+    //
+    //   case ...
+    //
+    //   default:
+    //     break;
+    //   }
+    // }
+    // ------------------------------------------------------------------------------
+    let event_handler = move |control_event| -> ServiceControlHandlerResult {
+        match control_event {
+            // Notifies a service to report its current status information to the service
+            // control manager. Always return NoError even if not implemented.
+            ServiceControl::Interrogate => {
+                ServiceControlHandlerResult::NoError
+            },
+
+            // Handle stop
+            ServiceControl::Stop => {
+                control_sender.send(HostControl::Stop).unwrap_or_default();
+                ServiceControlHandlerResult::NoError
+            },
+
+            // Handle pause and continue. The SCM only sends them if the service accepts
+            // PAUSE_CONTINUE.
+            ServiceControl::Pause => {
+                control_sender.send(HostControl::Pause).unwrap_or_default();
+                ServiceControlHandlerResult::NoError
+            },
+
+            ServiceControl::Continue => {
+                control_sender.send(HostControl::Continue).unwrap_or_default();
+                ServiceControlHandlerResult::NoError
+            },
+
+            // Handle system shutdown. The SCM only sends them if the service accepts SHUTDOWN
+            // (or PRESHUTDOWN). A service accepting PRESHUTDOWN gets the preshutdown
+            // notification before the system shutdown starts, and won't receive the shutdown
+            // notification any more.
+            ServiceControl::Shutdown => {
+                control_sender.send(HostControl::Shutdown).unwrap_or_default();
+                ServiceControlHandlerResult::NoError
+            },
+
+            ServiceControl::Preshutdown => {
+                control_sender.send(HostControl::Preshutdown).unwrap_or_default();
+                ServiceControlHandlerResult::NoError
+            },
+
+            // The SCM only sends it if the service accepts PARAM_CHANGE.
+            ServiceControl::ParamChange => {
+                control_sender.send(HostControl::ParamChange).unwrap_or_default();
+                ServiceControlHandlerResult::NoError
+            },
+
+            // The user-defined control codes (128 to 255) are always delivered, there is
+            // nothing to accept. They are handled by the command dispatcher of the host, so
+            // that the handler returns at once.
+            ServiceControl::UserEvent(code) => {
+                control_sender.send(HostControl::User(code.to_raw() as u8)).unwrap_or_default();
+                ServiceControlHandlerResult::NoError
+            },
+
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+
+    // Register system service event handler. The returned status handle should be used to
+    // report service status changes to the system.
+    //
+    // C++ equivalent
+    // ------------------------------------------------------------------------------
+    // g_StatusHandle = RegisterServiceCtrlHandler (SERVICE_NAME, EventHandler);
+    // ------------------------------------------------------------------------------
+    let status_handle = service_control_handler::register(&config.service_name, event_handler)
+        .map_err(|e| { ServiceError::caused_by(e, "Fail to register windows service. ").with_kind(ErrorKind::Platform) })?;
+
+    service_main(HostSession {
+        arguments,
+        reporter: Arc::new(ScmStatusReporter { status_handle, config }),
+        controls: control_receiver,
+    })
+}
+
+struct ScmStatusReporter {
+    status_handle: ServiceStatusHandle,
+    config: ServiceConfig,
+}
+
+impl StatusReporter for ScmStatusReporter {
+    fn report(&self, status: HostStatus) -> ServiceResult<()> {
+        // Each time we update the service status we need to tell the service controller what
+        // current status is, what kind of controls we can do next, what is the checkpoint value
+        let (desired_status, valid_controls) = match status.state {
+            HostState::StartPending => (ServiceState::StartPending, ServiceControlAccept::empty()),
+            HostState::Running => (ServiceState::Running, to_service_control_accept(self.config.accepted_controls)),
+            HostState::StopPending => (ServiceState::StopPending, ServiceControlAccept::empty()),
+            HostState::Stopped => (ServiceState::Stopped, ServiceControlAccept::empty()),
+            HostState::PausePending => (ServiceState::PausePending, ServiceControlAccept::empty()),
+            HostState::Paused => (ServiceState::Paused, to_service_control_accept(self.config.accepted_controls)),
+            HostState::ContinuePending => (ServiceState::ContinuePending, ServiceControlAccept::empty()),
+        };
+
+        log::info!("Setting service status for {}: {:?}.", self.config.service_name, desired_status);
+        self.status_handle.set_service_status(ServiceStatus {
+            service_type: to_windows_service_type(self.config.service_type),
+            current_state: desired_status,
+            controls_accepted: valid_controls,
+            exit_code: to_service_exit_code(status.exit_code),
+            checkpoint: status.checkpoint,
+            wait_hint: status.wait_hint,
+            process_id: None,
+        }).map_err(|e| {
+            let error_message = format!("Fail to set service status to {:?}. ", desired_status);
+            ServiceError::caused_by(e, &error_message).with_kind(ErrorKind::Platform)
+        })
+    }
+}
+
+fn to_service_exit_code(exit_code: u32) -> ServiceExitCode {
+    if exit_code == exit_code::SUCCESS {
+        ServiceExitCode::Win32(0)
+    } else {
+        ServiceExitCode::ServiceSpecific(exit_code)
+    }
+}
+
+fn to_windows_service_type(service_type: ServiceType) -> windows_service::service::ServiceType {
+    match service_type {
+        ServiceType::OwnProcess => windows_service::service::ServiceType::OWN_PROCESS,
+        ServiceType::ShareProcess => windows_service::service::ServiceType::SHARE_PROCESS,
+        ServiceType::UserOwnProcess => windows_service::service::ServiceType::USER_OWN_PROCESS,
+        ServiceType::UserShareProcess => windows_service::service::ServiceType::USER_SHARE_PROCESS,
+    }
+}
+
+fn to_service_control_accept(accepted_controls: AcceptedControls) -> ServiceControlAccept {
+    let mut service_control_accept = ServiceControlAccept::empty();
+    if accepted_controls.contains(AcceptedControls::STOP) {
+        service_control_accept |= ServiceControlAccept::STOP;
+    }
+    if accepted_controls.contains(AcceptedControls::PAUSE_CONTINUE) {
+        service_control_accept |= ServiceControlAccept::PAUSE_CONTINUE;
+    }
+    if accepted_controls.contains(AcceptedControls::SHUTDOWN) {
+        service_control_accept |= ServiceControlAccept::SHUTDOWN;
+    }
+    if accepted_controls.contains(AcceptedControls::PRESHUTDOWN) {
+        service_control_accept |= ServiceControlAccept::PRESHUTDOWN;
+    }
+    if accepted_controls.contains(AcceptedControls::PARAM_CHANGE) {
+        service_control_accept |= ServiceControlAccept::PARAM_CHANGE;
+    }
+    service_control_accept
+}
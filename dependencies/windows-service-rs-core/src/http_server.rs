@@ -0,0 +1,120 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::error::{ServiceError, ServiceResult};
+use crate::shutdown_token::ShutdownToken;
+
+// The listener is non-blocking, so that the server thread notices the stop request.
+const ACCEPT_POLLING_INTERVAL: Duration = Duration::from_millis(100);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) struct HttpResponse {
+    pub(crate) status: u16,
+    pub(crate) content_type: &'static str,
+    pub(crate) body: String,
+}
+
+impl HttpResponse {
+    pub(crate) fn json(status: u16, body: String) -> HttpResponse {
+        HttpResponse { status, content_type: "application/json", body }
+    }
+
+    pub(crate) fn text(status: u16, body: &str) -> HttpResponse {
+        HttpResponse { status, content_type: "text/plain; charset=utf-8", body: String::from(body) }
+    }
+}
+
+// Handles a GET request, given the path of the request without the query string.
+pub(crate) type HttpHandler = Box<dyn Fn(&str) -> HttpResponse + Send>;
+
+// A minimal HTTP server for the probes of the monitoring tools. It only listens on the loopback
+// interface and serves one request per connection, one connection at a time: the probes are
+// few and the responses are computed in advance, so there is no need for a full blown web
+// server.
+pub(crate) struct HttpServer {
+    token: ShutdownToken,
+    handle: JoinHandle<()>,
+}
+
+impl HttpServer {
+    pub(crate) fn start(port: u16, handler: HttpHandler) -> ServiceResult<HttpServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .and_then(|listener| { listener.set_nonblocking(true).map(|_| listener) })
+            .map_err(|e| { ServiceError::caused_by(e, &format!("Fail to listen on port {}. ", port)) })?;
+        let address = listener.local_addr().map_err(|e| { ServiceError::caused_by(e, "Fail to get the listening address. ") })?;
+        log::info!("Serving HTTP on {}.", address);
+
+        let token = ShutdownToken::new();
+        let token_for_thread = token.clone();
+        let handle = thread::spawn(move || { serve(listener, handler, token_for_thread) });
+        Ok(HttpServer { token, handle })
+    }
+
+    pub(crate) fn stop(self) {
+        self.token.cancel();
+        self.handle.join().unwrap_or_else(|e| {
+            log::error!("HTTP server error: {:?}", e);
+        });
+    }
+}
+
+fn serve(listener: TcpListener, handler: HttpHandler, token: ShutdownToken) {
+    while !token.is_cancelled() {
+        match listener.accept() {
+            Ok((stream, _)) => handle_connection(stream, &handler).unwrap_or_else(|e| {
+                log::warn!("Fail to serve the HTTP request: {}", e);
+            }),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                token.wait_timeout(ACCEPT_POLLING_INTERVAL);
+            },
+            Err(e) => {
+                log::warn!("Fail to accept the HTTP connection: {}", e);
+                token.wait_timeout(ACCEPT_POLLING_INTERVAL);
+            },
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, handler: &HttpHandler) -> io::Result<()> {
+    // The accepted stream may inherit the non-blocking mode of the listener.
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers don't matter, but they have to be read before the response is sent.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => handler(target.split('?').next().unwrap_or(target)),
+        (Some(_), Some(_)) => HttpResponse::text(405, "Method Not Allowed"),
+        _ => HttpResponse::text(400, "Bad Request"),
+    };
+
+    let mut writer = &stream;
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, reason_phrase(response.status), response.content_type, response.body.len(), response.body)?;
+    writer.flush()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
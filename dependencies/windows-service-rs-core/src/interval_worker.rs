@@ -0,0 +1,266 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::error::ServiceResult;
+use crate::random::random_unit;
+use crate::run_context::RunContext;
+
+// A zero interval would make the worker spin, the interval is at least this long.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What the worker does when a tick is late, E.g. because the previous tick took longer than
+/// the interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTicks {
+    /// The missed ticks run back to back until the worker has caught up with the cadence.
+    Burst,
+    /// The late tick runs at once, and the cadence restarts from it.
+    Delay,
+    /// The missed ticks are skipped, the next tick keeps the cadence.
+    Skip,
+}
+
+/// A tick of an interval worker.
+#[derive(Debug, Clone)]
+pub struct Tick {
+    /// The number of the tick, from 1.
+    pub number: u64,
+    /// The time the tick was due, before the jitter.
+    pub scheduled_at: Instant,
+}
+
+// The interval worker calls a tick function on a fixed cadence, which is the main loop of most
+// applications. For example:
+//
+// let worker = IntervalWorker::new(Duration::from_secs(2)).name("Worker 1").jitter(Duration::from_millis(200));
+// worker.run(context, |tick| { poll_the_queue(tick.number) })
+//
+// The ticks are due at start, start + interval, start + 2 * interval... so the duration of a
+// tick doesn't shift the next ones. The jitter delays each tick by a random duration up to the
+// given one (E.g. so that many instances don't hit a server at the same time), it doesn't shift
+// the next ones either. A tick which is late is handled according to `MissedTicks`, like the
+// `MissedTickBehavior` of Tokio.
+//
+// The sleep between two ticks returns at once when the shutdown token is cancelled, and the
+// worker returns Ok. An error of the tick function is returned at once. If the application
+// supports pausing, the worker parks the thread between two ticks while the service is paused,
+// and the cadence restarts when the service continues.
+//
+// The interval can be changed while the worker runs (E.g. on a configuration change), it
+// applies from the next tick.
+//
+// The worker sends a heartbeat before and after each tick (please refer to
+// `ApplicationOptions::heartbeat`), so a heartbeat timeout longer than the interval tells a
+// tick which hangs.
+pub struct IntervalWorker {
+    name: String,
+    interval_nanos: AtomicU64,
+    jitter: Duration,
+    missed_ticks: MissedTicks,
+    tick_timeout: Option<Duration>,
+}
+
+impl IntervalWorker {
+    /// A worker which bursts the missed ticks, without jitter nor tick timeout.
+    pub fn new(interval: Duration) -> IntervalWorker {
+        IntervalWorker {
+            name: String::from("Interval worker"),
+            interval_nanos: AtomicU64::new(to_nanos(interval)),
+            jitter: Duration::ZERO,
+            missed_ticks: MissedTicks::Burst,
+            tick_timeout: None,
+        }
+    }
+
+    /// The name of the worker in the logs.
+    pub fn name<N: Into<String>>(mut self, name: N) -> IntervalWorker {
+        self.name = name.into();
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Duration) -> IntervalWorker {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn missed_ticks(mut self, missed_ticks: MissedTicks) -> IntervalWorker {
+        self.missed_ticks = missed_ticks;
+        self
+    }
+
+    /// Logs a warning when a tick runs for longer than the timeout. The tick is not
+    /// interrupted.
+    pub fn tick_timeout(mut self, tick_timeout: Duration) -> IntervalWorker {
+        self.tick_timeout = Some(tick_timeout);
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_nanos(self.interval_nanos.load(Ordering::SeqCst))
+    }
+
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval_nanos.store(to_nanos(interval), Ordering::SeqCst);
+    }
+
+    pub fn run<F>(&self, context: &RunContext, mut tick: F) -> ServiceResult<()>
+        where F: FnMut(&Tick) -> ServiceResult<()> {
+        let token = context.shutdown_token();
+        let watchdog = self.tick_timeout.map(|timeout| { TickWatchdog::start(self.name.clone(), timeout) });
+        let mut number = 0;
+        let mut next = Instant::now();
+        let mut result = Ok(());
+        while !token.is_cancelled() {
+            let pause_signal = context.pause_signal();
+            if pause_signal.is_supported() && pause_signal.is_pause_requested() {
+                log::info!("{} is paused.", self.name);
+                pause_signal.wait_if_paused();
+                log::info!("{} is resumed.", self.name);
+                next = Instant::now();
+                continue;
+            }
+            let now = Instant::now();
+            if next > now {
+                if !token.sleep_or_cancel(next - now + self.jitter.mul_f64(random_unit())) {
+                    break;
+                }
+                continue;
+            }
+
+            number += 1;
+            if let Some(watchdog) = &watchdog {
+                watchdog.watch(Some(number));
+            }
+            context.heartbeat();
+            result = tick(&Tick { number, scheduled_at: next });
+            context.heartbeat();
+            if let Some(watchdog) = &watchdog {
+                watchdog.watch(None);
+            }
+            if result.is_err() {
+                break;
+            }
+            next = self.next_tick(next, Instant::now());
+        }
+        // The watchdog stops when it is dropped, even if a tick panics.
+        drop(watchdog);
+        if result.is_ok() {
+            log::info!("{} stops after {} tick(s) ({:?}).", self.name, number, context.stop_reason());
+        }
+        result
+    }
+
+    fn next_tick(&self, previous: Instant, now: Instant) -> Instant {
+        let interval = self.interval();
+        let next = previous + interval;
+        // A tick which is due right now is on time.
+        if next >= now {
+            return next;
+        }
+        let late = now - next;
+        match self.missed_ticks {
+            MissedTicks::Burst => next,
+            MissedTicks::Delay => now,
+            MissedTicks::Skip => {
+                // The next tick is the first one of the cadence from now.
+                let missed = late.as_nanos().div_ceil(interval.as_nanos());
+                log::warn!("{} is late by {:?}, skip {} tick(s).", self.name, late, missed);
+                next + interval.saturating_mul(missed.min(u32::MAX as u128) as u32)
+            },
+        }
+    }
+}
+
+fn to_nanos(interval: Duration) -> u64 {
+    interval.max(MIN_INTERVAL).as_nanos().min(u64::MAX as u128) as u64
+}
+
+// Warns when a tick runs for longer than the timeout. The tick can't be interrupted, so the
+// watchdog thread only tells which tick is stuck. The thread stops when the watchdog is dropped.
+struct TickWatchdog {
+    shared: Arc<(Mutex<WatchState>, Condvar)>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    // The tick in progress and its start.
+    current: Option<(u64, Instant)>,
+    warned: bool,
+    stopped: bool,
+}
+
+impl TickWatchdog {
+    fn start(name: String, timeout: Duration) -> TickWatchdog {
+        let shared: Arc<(Mutex<WatchState>, Condvar)> = Arc::default();
+        let shared_for_thread = shared.clone();
+        let handle = thread::spawn(move || {
+            let (_, changed) = &*shared_for_thread;
+            let mut state = lock(&shared_for_thread);
+            while !state.stopped {
+                let wait = match state.current {
+                    Some((number, started)) if !state.warned => {
+                        let elapsed = started.elapsed();
+                        if elapsed >= timeout {
+                            log::warn!("{} tick #{} has been running for {:?}, longer than {:?}.", name, number, elapsed, timeout);
+                            state.warned = true;
+                            None
+                        } else {
+                            Some(timeout - elapsed)
+                        }
+                    },
+                    _ => None,
+                };
+                state = match wait {
+                    Some(wait) => changed.wait_timeout(state, wait).map(|(state, _)| state).unwrap_or_else(|e| e.into_inner().0),
+                    None => changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+                };
+            }
+        });
+        TickWatchdog { shared, handle: Some(handle) }
+    }
+
+    fn watch(&self, tick: Option<u64>) {
+        let mut state = lock(&self.shared);
+        state.current = tick.map(|number| (number, Instant::now()));
+        state.warned = false;
+        self.shared.1.notify_all();
+    }
+
+}
+
+impl Drop for TickWatchdog {
+    fn drop(&mut self) {
+        lock(&self.shared).stopped = true;
+        self.shared.1.notify_all();
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap_or_else(|e| {
+                log::error!("Tick watchdog error: {:?}", e);
+            });
+        }
+    }
+}
+
+fn lock(shared: &(Mutex<WatchState>, Condvar)) -> MutexGuard<'_, WatchState> {
+    shared.0.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{IntervalWorker, MissedTicks};
+
+    #[test]
+    fn skip_keeps_the_cadence() {
+        let interval = Duration::from_secs(10);
+        let worker = IntervalWorker::new(interval).missed_ticks(MissedTicks::Skip);
+        let start = Instant::now();
+        // On time, nothing is skipped.
+        assert_eq!(worker.next_tick(start, start + interval), start + interval);
+        // Late by less than an interval, the late tick is skipped.
+        assert_eq!(worker.next_tick(start, start + interval + Duration::from_secs(3)), start + 2 * interval);
+        // Late by exactly an interval, the tick due now runs.
+        assert_eq!(worker.next_tick(start, start + 2 * interval), start + 2 * interval);
+    }
+}
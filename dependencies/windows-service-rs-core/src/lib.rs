@@ -1,4 +1,31 @@
 pub mod error;
+pub mod exit_code;
+#[cfg(windows)]
 pub mod win_dbg_logger;
 pub mod application;
+pub mod configuration;
+pub mod command;
+pub mod async_application;
+pub mod health;
+pub mod heartbeat;
+pub mod metrics;
+pub mod message_bus;
+pub mod scheduled_application;
+pub mod interval_worker;
+pub mod run_context;
+pub mod shutdown_token;
+pub mod pause_signal;
+pub mod registry;
+pub mod restart_policy;
+pub mod host;
 pub mod service_wrapper;
+mod supervisor;
+mod dependency;
+mod http_server;
+mod health_monitor;
+mod watchdog;
+mod endpoints;
+mod panic_guard;
+mod random;
+pub mod service_config;
+pub mod service_builder;
@@ -0,0 +1,567 @@
+use std::any::{self, Any, TypeId};
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::pin::pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::metrics::{Counter, MetricsRegistry};
+use crate::shutdown_token::ShutdownToken;
+
+// A publisher blocked on a full queue checks its shutdown token this often.
+const BLOCKED_PUBLISHER_POLLING_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a publisher does when the queue of a subscriber is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// The publisher waits for room in the queue, so no message is lost. It gives up if its
+    /// application is requested to exit, please refer to `Publisher::publish`.
+    Block,
+    /// The new message is dropped (for this subscriber only).
+    DropNewest,
+    /// The oldest message in the queue is dropped to make room for the new one.
+    DropOldest,
+}
+
+// The message bus carries messages between the applications of the service. A message is
+// published on a named topic, and each subscriber of the topic gets a clone of it in its own
+// bounded queue. A topic carries one type of payload, given by the first publisher or subscriber.
+// For example:
+//
+// // The ingest application.
+// let orders = context.message_bus().publisher::<Order>("orders")?;
+// orders.publish(order)?;
+//
+// // The publisher application, it subscribes in `initialize` so that it misses no message.
+// let orders = context.message_bus().subscribe::<Order>("orders", 100, Overflow::Block)?;
+// while let Some(order) = orders.recv() {
+//     publish(order)?;
+// }
+//
+// A message published while a topic has no subscriber is discarded. The queue of a subscriber
+// goes away with it, so the messages queued for an instance which is restarted are lost.
+//
+// When the service stops, the host closes the topics so that the queued messages are delivered:
+// once an application is requested to stop, the topics it subscribes to are closed as soon as
+// their publishing applications have exited. The subscriber gets the queued messages, then
+// `recv` returns None. So the publishing application has to stop before the subscribing
+// application: it depends on the subscriber (please refer to `ApplicationOptions::depends_on`).
+// A publishing application which stops later is not waited for, its messages are lost. A
+// publisher which waits for room in a full queue gives up when its application is requested to
+// exit, so that a subscriber which doesn't consume any more doesn't hold the stop of the service.
+//
+// Cloning the bus is cheap, all the clones share the topics.
+#[derive(Clone)]
+pub struct MessageBus {
+    shared: Arc<BusState>,
+    // The index of the application which uses the bus, if it is the bus of a run context.
+    owner: Option<usize>,
+    // The shutdown token of the run context, the publishers stop waiting when it is cancelled.
+    token: Option<ShutdownToken>,
+}
+
+struct BusState {
+    topics: Mutex<HashMap<String, Topic>>,
+    metrics: MetricsRegistry,
+}
+
+struct Topic {
+    payload: TypeId,
+    payload_name: &'static str,
+    subscribers: Vec<TopicSubscriber>,
+    next_subscriber_id: u64,
+    // The applications which have a publisher on the topic, until they exit.
+    publishers: Vec<usize>,
+    // A subscribing application is requested to stop, the topic is closed once the publishers
+    // have exited.
+    draining: bool,
+    closed: bool,
+    published: Counter,
+    dropped: Counter,
+}
+
+struct TopicSubscriber {
+    id: u64,
+    owner: Option<usize>,
+    // The same queue, typed (to deliver messages) and untyped (to close it).
+    queue: Arc<dyn Any + Send + Sync>,
+    control: Arc<dyn QueueControl>,
+}
+
+impl Topic {
+    fn close(&mut self) {
+        self.closed = true;
+        self.subscribers.iter().for_each(|subscriber| subscriber.control.close());
+    }
+}
+
+impl Default for MessageBus {
+    fn default() -> Self {
+        MessageBus::with_metrics(MetricsRegistry::new())
+    }
+}
+
+impl MessageBus {
+    pub fn new() -> MessageBus {
+        MessageBus::default()
+    }
+
+    pub(crate) fn with_metrics(metrics: MetricsRegistry) -> MessageBus {
+        MessageBus { shared: Arc::new(BusState { topics: Mutex::new(HashMap::new()), metrics }), owner: None, token: None }
+    }
+
+    // The bus as seen by an application, so that the host knows who publishes and subscribes.
+    pub(crate) fn for_application(&self, index: usize) -> MessageBus {
+        MessageBus { shared: self.shared.clone(), owner: Some(index), token: self.token.clone() }
+    }
+
+    // The bus as seen by a run context, so that its publishers stop waiting when the
+    // application is requested to exit.
+    pub(crate) fn with_token(&self, token: ShutdownToken) -> MessageBus {
+        MessageBus { shared: self.shared.clone(), owner: self.owner, token: Some(token) }
+    }
+
+    /// Fails if the topic carries another type of payload, or if it is closed.
+    pub fn publisher<T: Clone + Send + 'static>(&self, topic: &str) -> ServiceResult<Publisher<T>> {
+        let mut topics = self.shared.topics();
+        let entry = self.shared.topic::<T>(&mut topics, topic)?;
+        if entry.closed {
+            return Err(ServiceError::of_kind(ErrorKind::Cancelled, format!("Topic '{}' is closed. ", topic)));
+        }
+        if let Some(owner) = self.owner {
+            if !entry.publishers.contains(&owner) {
+                entry.publishers.push(owner);
+            }
+        }
+        Ok(Publisher { bus: self.shared.clone(), topic: String::from(topic), token: self.token.clone(), payload: PhantomData })
+    }
+
+    /// Subscribes to the topic with a queue of the given capacity. Fails if the topic carries
+    /// another type of payload, or if it is closed.
+    pub fn subscribe<T: Clone + Send + 'static>(&self, topic: &str, capacity: usize, overflow: Overflow) -> ServiceResult<Subscriber<T>> {
+        if capacity == 0 {
+            return Err(ServiceError::of_kind(ErrorKind::Config, format!("The queue of a subscriber of topic '{}' must hold a message at least. ", topic)));
+        }
+        let mut topics = self.shared.topics();
+        let entry = self.shared.topic::<T>(&mut topics, topic)?;
+        if entry.closed {
+            return Err(ServiceError::of_kind(ErrorKind::Cancelled, format!("Topic '{}' is closed. ", topic)));
+        }
+        let queue = Arc::new(Queue::<T>::new(capacity, overflow));
+        let id = entry.next_subscriber_id;
+        entry.next_subscriber_id += 1;
+        entry.subscribers.push(TopicSubscriber { id, owner: self.owner, queue: queue.clone(), control: queue.clone() });
+        Ok(Subscriber { bus: self.shared.clone(), topic: String::from(topic), id, queue })
+    }
+
+    // The application is requested to stop: the topics it subscribes to close once their
+    // publishers have exited. The application itself and the publishers which stop later are
+    // not waited for.
+    pub(crate) fn drain<F: Fn(usize) -> bool>(&self, index: usize, stops_later: F) {
+        let mut topics = self.shared.topics();
+        for (name, topic) in topics.iter_mut() {
+            if topic.closed || !topic.subscribers.iter().any(|subscriber| subscriber.owner == Some(index)) {
+                continue;
+            }
+            topic.draining = true;
+            topic.publishers.retain(|publisher| {
+                if stops_later(*publisher) {
+                    log::warn!(
+                        "Application #{} publishes on topic '{}' and stops after its subscriber, application #{}. Make it depend on the subscriber.",
+                        publisher, name, index);
+                }
+                *publisher != index && !stops_later(*publisher)
+            });
+            if topic.publishers.is_empty() {
+                log::info!("Topic '{}' is closed.", name);
+                topic.close();
+            }
+        }
+    }
+
+    // The application has exited for good, it doesn't publish any more.
+    pub(crate) fn close_application(&self, index: usize) {
+        let mut topics = self.shared.topics();
+        for (name, topic) in topics.iter_mut() {
+            topic.publishers.retain(|publisher| *publisher != index);
+            if topic.draining && !topic.closed && topic.publishers.is_empty() {
+                log::info!("Topic '{}' is closed.", name);
+                topic.close();
+            }
+        }
+    }
+
+    // Closes all the topics once the applications have exited.
+    pub(crate) fn close(&self) {
+        self.shared.topics().values_mut().for_each(|topic| topic.close());
+    }
+}
+
+impl BusState {
+    fn topics(&self) -> MutexGuard<'_, HashMap<String, Topic>> {
+        self.topics.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Returns the topic, registering it the first time. A topic carries one type of payload.
+    fn topic<'a, T: 'static>(&self, topics: &'a mut HashMap<String, Topic>, name: &str) -> ServiceResult<&'a mut Topic> {
+        let metrics = self.metrics.with_label("topic", name);
+        let topic = topics.entry(String::from(name)).or_insert_with(|| Topic {
+            payload: TypeId::of::<T>(),
+            payload_name: any::type_name::<T>(),
+            subscribers: vec![],
+            next_subscriber_id: 0,
+            publishers: vec![],
+            draining: false,
+            closed: false,
+            published: metrics.counter("service_bus_messages_published_total", "The messages published on the topic."),
+            dropped: metrics.counter("service_bus_messages_dropped_total", "The messages dropped because a queue is full."),
+        });
+        if topic.payload != TypeId::of::<T>() {
+            return Err(ServiceError::of_kind(ErrorKind::Config, format!(
+                "Topic '{}' carries {}, not {}. ", name, topic.payload_name, any::type_name::<T>())));
+        }
+        Ok(topic)
+    }
+
+    fn delivery<T: Send + 'static>(&self, name: &str) -> ServiceResult<Delivery<T>> {
+        let topics = self.topics();
+        let topic = topics.get(name).ok_or_else(|| { ServiceError::of_kind(ErrorKind::Config, format!("Topic '{}' doesn't exist. ", name)) })?;
+        if topic.closed {
+            return Err(ServiceError::of_kind(ErrorKind::Cancelled, format!("Topic '{}' is closed. ", name)));
+        }
+        let queues = topic.subscribers.iter()
+            .filter_map(|subscriber| subscriber.queue.clone().downcast::<Queue<T>>().ok())
+            .collect();
+        Ok(Delivery { queues, published: topic.published.clone(), dropped: topic.dropped.clone() })
+    }
+
+    fn unsubscribe(&self, name: &str, id: u64) {
+        if let Some(topic) = self.topics().get_mut(name) {
+            topic.subscribers.retain(|subscriber| subscriber.id != id);
+        }
+    }
+}
+
+// The queues of the subscribers of a topic, and the counters of the topic.
+struct Delivery<T> {
+    queues: Vec<Arc<Queue<T>>>,
+    published: Counter,
+    dropped: Counter,
+}
+
+/// Publishes messages on a topic. Please refer to `MessageBus::publisher`.
+pub struct Publisher<T> {
+    bus: Arc<BusState>,
+    topic: String,
+    token: Option<ShutdownToken>,
+    payload: PhantomData<fn(T)>,
+}
+
+impl<T: Clone + Send + 'static> Publisher<T> {
+    /// Delivers a clone of the message to each subscriber. With `Overflow::Block`, it waits for
+    /// room in the queues, which blocks the thread: an async application uses `publish_async`.
+    /// Fails if the topic is closed, or if the application is requested to exit while it
+    /// waits (the message is dropped for the remaining subscribers).
+    pub fn publish(&self, message: T) -> ServiceResult<()> {
+        let delivery = self.bus.delivery::<T>(&self.topic)?;
+        delivery.published.inc();
+        for (queue, message) in clones(delivery.queues, message) {
+            match queue.push(message, self.token.as_ref()) {
+                Some(true) => {},
+                Some(false) => delivery.dropped.inc(),
+                None => return Err(self.cancelled(&delivery.dropped)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The async counterpart of `publish`, it waits for room in the queues without blocking
+    /// the thread.
+    pub async fn publish_async(&self, message: T) -> ServiceResult<()> {
+        let delivery = self.bus.delivery::<T>(&self.topic)?;
+        delivery.published.inc();
+        for (queue, message) in clones(delivery.queues, message) {
+            match queue.push_async(message, self.token.as_ref()).await {
+                Some(true) => {},
+                Some(false) => delivery.dropped.inc(),
+                None => return Err(self.cancelled(&delivery.dropped)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    fn cancelled(&self, dropped: &Counter) -> ServiceError {
+        dropped.inc();
+        ServiceError::of_kind(ErrorKind::Cancelled, format!(
+            "The message on topic '{}' is not delivered, the application is requested to exit. ", self.topic))
+    }
+}
+
+// One message per queue, the last queue gets the message itself.
+fn clones<T: Clone>(queues: Vec<Arc<Queue<T>>>, message: T) -> Vec<(Arc<Queue<T>>, T)> {
+    let mut message = Some(message);
+    let count = queues.len();
+    queues.into_iter().enumerate()
+        .filter_map(|(index, queue)| {
+            let message = if index + 1 == count { message.take() } else { message.clone() };
+            message.map(|message| (queue, message))
+        })
+        .collect()
+}
+
+/// Receives the messages of a topic. Dropping the subscriber unsubscribes it. Please refer to
+/// `MessageBus::subscribe`.
+pub struct Subscriber<T> {
+    bus: Arc<BusState>,
+    topic: String,
+    id: u64,
+    queue: Arc<Queue<T>>,
+}
+
+impl<T: Send + 'static> Subscriber<T> {
+    /// Waits for the next message. Returns None once the topic is closed and the queue is
+    /// empty.
+    pub fn recv(&self) -> Option<T> {
+        self.queue.pop(None)
+    }
+
+    /// Returns None if there is no message within the timeout, or if the topic is closed and
+    /// the queue is empty (please refer to `is_closed`).
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        self.queue.pop(Some(Instant::now() + timeout))
+    }
+
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.pop(Some(Instant::now()))
+    }
+
+    /// The async counterpart of `recv`.
+    pub async fn recv_async(&self) -> Option<T> {
+        self.queue.pop_async().await
+    }
+
+    /// Whether the topic is closed and all the queued messages are received.
+    pub fn is_closed(&self) -> bool {
+        let state = self.queue.state();
+        state.closed && state.messages.is_empty()
+    }
+
+    /// The number of messages in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.state().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(&self.topic, self.id);
+        self.queue.detach();
+    }
+}
+
+// The bounded queue of a subscriber. The blocked threads wait on the condition variable, and the
+// blocked tasks on the notify.
+struct Queue<T> {
+    capacity: usize,
+    overflow: Overflow,
+    state: Mutex<QueueState<T>>,
+    changed: Condvar,
+    notify: Notify,
+}
+
+struct QueueState<T> {
+    messages: VecDeque<T>,
+    // The topic is closed, no message comes any more.
+    closed: bool,
+    // The subscriber is gone, the messages are not delivered any more.
+    detached: bool,
+}
+
+trait QueueControl: Send + Sync {
+    fn close(&self);
+}
+
+impl<T: Send> QueueControl for Queue<T> {
+    fn close(&self) {
+        self.state().closed = true;
+        self.wake();
+    }
+}
+
+enum Push<T> {
+    Done(bool),
+    Full(T),
+}
+
+impl<T> Queue<T> {
+    fn new(capacity: usize, overflow: Overflow) -> Queue<T> {
+        Queue {
+            capacity,
+            overflow,
+            state: Mutex::new(QueueState { messages: VecDeque::with_capacity(capacity), closed: false, detached: false }),
+            changed: Condvar::new(),
+            notify: Notify::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, QueueState<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wake(&self) {
+        self.changed.notify_all();
+        self.notify.notify_waiters();
+    }
+
+    fn detach(&self) {
+        let mut state = self.state();
+        state.detached = true;
+        state.messages.clear();
+        drop(state);
+        self.wake();
+    }
+
+    // Returns whether the message is queued, or Full if the publisher has to wait.
+    fn try_push(&self, state: &mut QueueState<T>, message: T) -> Push<T> {
+        if state.detached || state.closed {
+            return Push::Done(false);
+        }
+        let mut delivered = true;
+        if state.messages.len() >= self.capacity {
+            match self.overflow {
+                Overflow::Block => return Push::Full(message),
+                Overflow::DropNewest => return Push::Done(false),
+                Overflow::DropOldest => {
+                    state.messages.pop_front();
+                    delivered = false;
+                },
+            }
+        }
+        state.messages.push_back(message);
+        Push::Done(delivered)
+    }
+
+    // Returns false if a message is dropped, None if the token is cancelled while the queue is
+    // full.
+    fn push(&self, message: T, token: Option<&ShutdownToken>) -> Option<bool> {
+        let mut state = self.state();
+        let mut message = message;
+        loop {
+            match self.try_push(&mut state, message) {
+                Push::Done(delivered) => {
+                    drop(state);
+                    self.wake();
+                    return Some(delivered);
+                },
+                Push::Full(_) if token.is_some_and(ShutdownToken::is_cancelled) => return None,
+                Push::Full(returned) => {
+                    message = returned;
+                    state = self.changed.wait_timeout(state, BLOCKED_PUBLISHER_POLLING_INTERVAL).map(|(state, _)| state)
+                        .unwrap_or_else(|e| e.into_inner().0);
+                },
+            }
+        }
+    }
+
+    async fn push_async(&self, message: T, token: Option<&ShutdownToken>) -> Option<bool> {
+        let mut message = message;
+        loop {
+            // Registered before the check, so that no wake up is missed in between.
+            let mut notified = pin!(self.notify.notified());
+            notified.as_mut().enable();
+            match self.try_push(&mut self.state(), message) {
+                Push::Done(delivered) => {
+                    self.wake();
+                    return Some(delivered);
+                },
+                Push::Full(_) if token.is_some_and(ShutdownToken::is_cancelled) => return None,
+                Push::Full(returned) => message = returned,
+            }
+            tokio::time::timeout(BLOCKED_PUBLISHER_POLLING_INTERVAL, notified).await.unwrap_or_default();
+        }
+    }
+
+    // Waits for a message until the deadline, if any.
+    fn pop(&self, deadline: Option<Instant>) -> Option<T> {
+        let mut state = self.state();
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                drop(state);
+                self.wake();
+                return Some(message);
+            }
+            if state.closed {
+                return None;
+            }
+            state = match deadline {
+                None => self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    self.changed.wait_timeout(state, deadline - now).map(|(state, _)| state)
+                        .unwrap_or_else(|e| e.into_inner().0)
+                },
+            };
+        }
+    }
+
+    async fn pop_async(&self) -> Option<T> {
+        loop {
+            let mut notified = pin!(self.notify.notified());
+            notified.as_mut().enable();
+            {
+                let mut state = self.state();
+                if let Some(message) = state.messages.pop_front() {
+                    drop(state);
+                    self.wake();
+                    return Some(message);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use crate::error::ErrorKind;
+    use crate::shutdown_token::ShutdownToken;
+    use super::{MessageBus, Overflow};
+
+    #[test]
+    fn blocked_publisher_gives_up_when_cancelled() {
+        let token = ShutdownToken::new();
+        let bus = MessageBus::new().with_token(token.clone());
+        let _subscriber = bus.subscribe::<u32>("numbers", 1, Overflow::Block).unwrap();
+        let publisher = bus.publisher::<u32>("numbers").unwrap();
+        publisher.publish(1).unwrap();
+
+        let cancel = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            token.cancel();
+        });
+        let error = publisher.publish(2).unwrap_err();
+        cancel.join().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Cancelled);
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The default buckets of a histogram, in seconds. They fit the duration of a request.
+pub const DEFAULT_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// The metrics registry holds the metrics of the service: the built-in metrics of the host and
+// the metrics of the business applications. They are exposed in the Prometheus text format,
+// please refer to `ServiceBuilder::metrics_port`.
+//
+// Getting a metric registers it the first time, and returns the same metric afterwards, so an
+// application can get its metrics in `run` even though it is restarted with a fresh instance.
+// For example:
+//
+// let processed = context.metrics().counter("orders_processed_total", "The processed orders.");
+// processed.inc();
+//
+// The registry of an application (`RunContext::metrics`) adds the `application` label to all
+// the metrics, and `with_label` adds more. Cloning the registry is cheap, the clones share the
+// metrics.
+#[derive(Clone, Default)]
+pub struct MetricsRegistry {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+    labels: Vec<(String, String)>,
+}
+
+struct Family {
+    help: String,
+    metrics: BTreeMap<Vec<(String, String)>, Metric>,
+}
+
+#[derive(Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+}
+
+impl MetricsRegistry {
+    pub fn new() -> MetricsRegistry {
+        MetricsRegistry::default()
+    }
+
+    /// Returns a view of the registry which adds the label to the metrics it returns.
+    pub fn with_label(&self, name: &str, value: &str) -> MetricsRegistry {
+        let mut labels = self.labels.clone();
+        labels.push((String::from(name), String::from(value)));
+        MetricsRegistry { families: self.families.clone(), labels }
+    }
+
+    pub fn counter(&self, name: &str, help: &str) -> Counter {
+        match self.metric(name, help, Metric::Counter(Counter::default())) {
+            Metric::Counter(counter) => counter,
+            _ => Counter::default(),
+        }
+    }
+
+    pub fn gauge(&self, name: &str, help: &str) -> Gauge {
+        match self.metric(name, help, Metric::Gauge(Gauge::default())) {
+            Metric::Gauge(gauge) => gauge,
+            _ => Gauge::default(),
+        }
+    }
+
+    /// A histogram with `DEFAULT_BUCKETS`.
+    pub fn histogram(&self, name: &str, help: &str) -> Histogram {
+        self.histogram_with_buckets(name, help, &DEFAULT_BUCKETS)
+    }
+
+    /// The buckets are the upper bounds, in ascending order. The `+Inf` bucket is implicit.
+    pub fn histogram_with_buckets(&self, name: &str, help: &str, buckets: &[f64]) -> Histogram {
+        match self.metric(name, help, Metric::Histogram(Histogram::new(buckets))) {
+            Metric::Histogram(histogram) => histogram,
+            _ => Histogram::new(buckets),
+        }
+    }
+
+    // Returns the registered metric, or registers the new one. A metric registered with another
+    // type is an error of the application: it gets the new metric, which is not exposed.
+    fn metric(&self, name: &str, help: &str, new_metric: Metric) -> Metric {
+        let mut families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        let family = families.entry(String::from(name))
+            .or_insert_with(|| { Family { help: String::from(help), metrics: BTreeMap::new() } });
+        if let Some(existing) = family.metrics.values().next() {
+            if existing.kind() != new_metric.kind() {
+                log::error!("Metric {} is a {}, it can't be used as a {}.", name, existing.kind(), new_metric.kind());
+                return new_metric;
+            }
+        }
+        family.metrics.entry(self.labels.clone()).or_insert(new_metric).clone()
+    }
+
+    /// Renders all the metrics in the Prometheus text format (version 0.0.4).
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        let mut text = String::new();
+        for (name, family) in families.iter() {
+            let kind = match family.metrics.values().next() {
+                Some(metric) => metric.kind(),
+                None => continue,
+            };
+            writeln!(text, "# HELP {} {}", name, escape_help(&family.help)).unwrap_or_default();
+            writeln!(text, "# TYPE {} {}", name, kind).unwrap_or_default();
+            for (labels, metric) in family.metrics.iter() {
+                match metric {
+                    Metric::Counter(counter) =>
+                        writeln!(text, "{}{} {}", name, format_labels(labels, None), counter.get()).unwrap_or_default(),
+                    Metric::Gauge(gauge) =>
+                        writeln!(text, "{}{} {}", name, format_labels(labels, None), format_value(gauge.get())).unwrap_or_default(),
+                    Metric::Histogram(histogram) => histogram.render(&mut text, name, labels),
+                }
+            }
+        }
+        text
+    }
+}
+
+/// A value which only goes up, E.g. the number of processed requests.
+#[derive(Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value which goes up and down, E.g. the number of items in a queue.
+#[derive(Clone, Default)]
+pub struct Gauge(Arc<AtomicF64>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.set(value);
+    }
+
+    pub fn add(&self, value: f64) {
+        self.0.add(value);
+    }
+
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1.0);
+    }
+
+    pub fn get(&self) -> f64 {
+        self.0.get()
+    }
+}
+
+/// Counts the observed values (E.g. durations in seconds) in buckets.
+#[derive(Clone)]
+pub struct Histogram(Arc<HistogramState>);
+
+struct HistogramState {
+    buckets: Vec<f64>,
+    // The count of each bucket, not cumulative. The last one is the `+Inf` bucket.
+    counts: Vec<AtomicU64>,
+    sum: AtomicF64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Histogram {
+        Histogram(Arc::new(HistogramState {
+            buckets: buckets.to_vec(),
+            counts: (0..=buckets.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicF64::default(),
+        }))
+    }
+
+    pub fn observe(&self, value: f64) {
+        let state = &self.0;
+        let bucket = state.buckets.iter().position(|bound| value <= *bound).unwrap_or(state.buckets.len());
+        state.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        state.sum.add(value);
+    }
+
+    // The buckets are cumulative in the text format.
+    fn render(&self, text: &mut String, name: &str, labels: &[(String, String)]) {
+        let state = &self.0;
+        let mut count = 0;
+        for (index, bucket_count) in state.counts.iter().enumerate() {
+            count += bucket_count.load(Ordering::Relaxed);
+            let bound = state.buckets.get(index).copied().unwrap_or(f64::INFINITY);
+            let le = format_value(bound);
+            writeln!(text, "{}_bucket{} {}", name, format_labels(labels, Some(&le)), count).unwrap_or_default();
+        }
+        writeln!(text, "{}_sum{} {}", name, format_labels(labels, None), format_value(state.sum.get())).unwrap_or_default();
+        writeln!(text, "{}_count{} {}", name, format_labels(labels, None), count).unwrap_or_default();
+    }
+}
+
+// There is no atomic float in the standard library, the bits of the float are stored instead.
+#[derive(Default)]
+struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    fn add(&self, value: f64) {
+        self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + value).to_bits())
+        }).unwrap_or_default();
+    }
+}
+
+fn format_labels(labels: &[(String, String)], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels.iter()
+        .map(|(name, value)| { format!("{}=\"{}\"", name, escape_label_value(value)) })
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() { String::new() } else { format!("{{{}}}", pairs.join(",")) }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value.is_infinite() {
+        String::from(if value > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
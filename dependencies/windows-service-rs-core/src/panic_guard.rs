@@ -0,0 +1,81 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Once;
+use std::task::{Context, Poll};
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+
+// The payload of a panic only carries the panic message. The location and the backtrace are only
+// available to the panic hook, so the hook records them for the thread which panics, and the
+// guard picks them up after unwinding.
+thread_local! {
+    static LAST_PANIC: RefCell<Option<(String, Backtrace)>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+// Calls the function and converts a panic into a `ServiceError` carrying the panic message, the
+// location and the backtrace. The identity tells which application panics.
+pub(crate) fn call<T, F: FnOnce() -> ServiceResult<T>>(identity: &str, function: F) -> ServiceResult<T> {
+    install_hook();
+    panic::catch_unwind(AssertUnwindSafe(function)).unwrap_or_else(|payload| {
+        Err(panic_error(identity, payload.as_ref()))
+    })
+}
+
+// The async counterpart of `call`: the future is polled within `catch_unwind`, so a panic is
+// caught on the worker thread which polls the future, where the panic hook has recorded the
+// location and the backtrace.
+pub(crate) fn call_async<T, F>(identity: &str, future: F) -> CatchPanic<F>
+    where F: Future<Output = ServiceResult<T>> {
+    install_hook();
+    CatchPanic { identity: String::from(identity), future: Box::pin(future) }
+}
+
+pub(crate) struct CatchPanic<F> {
+    identity: String,
+    future: Pin<Box<F>>,
+}
+
+impl<T, F: Future<Output = ServiceResult<T>>> Future for CatchPanic<F> {
+    type Output = ServiceResult<T>;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        panic::catch_unwind(AssertUnwindSafe(|| { this.future.as_mut().poll(context) }))
+            .unwrap_or_else(|payload| { Poll::Ready(Err(panic_error(&this.identity, payload.as_ref()))) })
+    }
+}
+
+fn panic_error(identity: &str, payload: &(dyn Any + Send)) -> ServiceError {
+    let (location, backtrace) = LAST_PANIC.with(|last_panic| { last_panic.borrow_mut().take() })
+        .map(|(location, backtrace)| { (location, backtrace.to_string()) })
+        .unwrap_or_else(|| { (String::from("<unknown>"), String::from("<unavailable>")) });
+    ServiceError::of_kind(ErrorKind::Application, format!(
+        "{} panicked at {}: {}\nstack backtrace:\n{}",
+        identity, location, panic_message(payload), backtrace))
+}
+
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let location = info.location()
+                .map(|location| { location.to_string() })
+                .unwrap_or_else(|| { String::from("<unknown>") });
+            LAST_PANIC.with(|last_panic| {
+                *last_panic.borrow_mut() = Some((location, Backtrace::force_capture()));
+            });
+            previous_hook(info);
+        }));
+    });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload.downcast_ref::<&str>().copied()
+        .or_else(|| { payload.downcast_ref::<String>().map(|message| message.as_str()) })
+        .unwrap_or("<non-string panic payload>")
+}
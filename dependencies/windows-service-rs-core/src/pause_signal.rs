@@ -0,0 +1,73 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+// The pause signal is shared by the host and one application. The host requests the application
+// to pause (or to continue), and the application acknowledges the request by parking its thread
+// in `wait_if_paused` until the host asks it to continue. The service is reported as paused only
+// when every application which supports pausing has acknowledged the request.
+#[derive(Clone, Default)]
+pub struct PauseSignal {
+    shared: Arc<(Mutex<PauseState>, Condvar)>,
+}
+
+#[derive(Default)]
+struct PauseState {
+    supported: bool,
+    requested: bool,
+    paused: bool,
+}
+
+impl PauseSignal {
+    pub fn new() -> PauseSignal {
+        PauseSignal::default()
+    }
+
+    pub fn is_pause_requested(&self) -> bool {
+        self.lock().requested
+    }
+
+    /// Blocks the calling thread while the host keeps the service paused. Returns immediately if
+    /// no pause is requested. Call it at a point where the application can be safely suspended,
+    /// E.g. at the beginning of each iteration of the main loop.
+    pub fn wait_if_paused(&self) {
+        let (_, changed) = &*self.shared;
+        let mut state = self.lock();
+        if !state.requested {
+            return;
+        }
+
+        state.paused = true;
+        changed.notify_all();
+        while state.requested {
+            state = changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.paused = false;
+        changed.notify_all();
+    }
+
+    pub(crate) fn set_supported(&self, supported: bool) {
+        self.lock().supported = supported;
+    }
+
+    pub(crate) fn is_supported(&self) -> bool {
+        self.lock().supported
+    }
+
+    pub(crate) fn request_pause(&self) {
+        self.lock().requested = true;
+    }
+
+    pub(crate) fn request_continue(&self) {
+        let (_, changed) = &*self.shared;
+        self.lock().requested = false;
+        changed.notify_all();
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PauseState> {
+        let (state, _) = &*self.shared;
+        state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,361 @@
+use std::sync::Arc;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use crate::application::SimpleApplication;
+use crate::async_application::AsyncApplication;
+use crate::command::{self, ScaleCommands, UserCommand};
+use crate::configuration::Configuration;
+use crate::dependency;
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::heartbeat::{HeartbeatPolicy, MissedHeartbeat};
+use crate::restart_policy::RestartPolicy;
+
+/// Creates a new instance of a business application. The factory can capture configuration or
+/// shared state, since it is a closure rather than a plain function. The instance must be `Sync`
+/// because the host calls it from other threads while `run` is running (`health`,
+/// `on_config_changed` and `on_command`), so an application keeps its mutable state in a `Mutex`
+/// or an atomic rather than in a `Cell` or a `RefCell`.
+pub type ApplicationFactory = Box<dyn Fn() -> Box<dyn SimpleApplication + Send + Sync> + Send + Sync>;
+
+/// Creates a new instance of an async business application.
+pub type AsyncApplicationFactory = Box<dyn Fn() -> Arc<dyn AsyncApplication> + Send + Sync>;
+
+/// Creates a new instance of a business application from the configuration of the service.
+pub type ConfiguredApplicationFactory =
+    Box<dyn Fn(&Configuration) -> ServiceResult<Box<dyn SimpleApplication + Send + Sync>> + Send + Sync>;
+
+/// Creates a new instance of an async business application from the configuration of the service.
+pub type ConfiguredAsyncApplicationFactory =
+    Box<dyn Fn(&Configuration) -> ServiceResult<Arc<dyn AsyncApplication>> + Send + Sync>;
+
+// Checks that the configuration section of a configured application is valid.
+type ConfigurationCheck = Arc<dyn Fn(&Configuration) -> ServiceResult<()> + Send + Sync>;
+
+#[derive(Clone)]
+pub enum RegisteredFactory {
+    Simple(Arc<ApplicationFactory>),
+    Async(Arc<AsyncApplicationFactory>),
+    ConfiguredSimple(Arc<ConfiguredApplicationFactory>),
+    ConfiguredAsync(Arc<ConfiguredAsyncApplicationFactory>),
+}
+
+/// How the host runs an application.
+#[derive(Debug, Clone)]
+pub struct ApplicationOptions {
+    pub restart_policy: RestartPolicy,
+    /// The service stops if a critical application fails for good (E.g. it fails and is not
+    /// restarted). The failure of an optional application only stops the application itself.
+    pub critical: bool,
+    /// The name of the application, which other applications use to depend on it. It shows in
+    /// the logs as well.
+    pub name: Option<String>,
+    /// The names of the applications this application depends on. It is initialized after them
+    /// and stopped before them.
+    pub dependencies: Vec<String>,
+    /// The user-defined commands the application handles, please refer to the `command`
+    /// module.
+    pub commands: Vec<UserCommand>,
+    /// The number of identical instances the host runs, 1 by default. The `replicas` section
+    /// of the configuration overrides it, please refer to `Configuration::replicas`.
+    pub replicas: usize,
+    /// The control codes which add or remove a replica while the service runs.
+    pub scale_commands: Option<ScaleCommands>,
+    /// The heartbeat the application sends while it runs, if the host watches it for hangs.
+    pub heartbeat: Option<HeartbeatPolicy>,
+}
+
+impl Default for ApplicationOptions {
+    fn default() -> Self {
+        ApplicationOptions {
+            restart_policy: RestartPolicy::default(),
+            critical: false,
+            name: None,
+            dependencies: vec![],
+            commands: vec![],
+            replicas: 1,
+            scale_commands: None,
+            heartbeat: None,
+        }
+    }
+}
+
+impl ApplicationOptions {
+    pub fn new() -> ApplicationOptions {
+        ApplicationOptions::default()
+    }
+
+    pub fn restart_policy(mut self, restart_policy: RestartPolicy) -> ApplicationOptions {
+        self.restart_policy = restart_policy;
+        self
+    }
+
+    pub fn critical(mut self, critical: bool) -> ApplicationOptions {
+        self.critical = critical;
+        self
+    }
+
+    pub fn name<N: Into<String>>(mut self, name: N) -> ApplicationOptions {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn depends_on<N: Into<String>>(mut self, dependency: N) -> ApplicationOptions {
+        self.dependencies.push(dependency.into());
+        self
+    }
+
+    /// Maps a user-defined control code (128 to 255) to a command of the application, which
+    /// the host delivers to `SimpleApplication::on_command`.
+    pub fn command<N: Into<String>>(mut self, name: N, code: u8) -> ApplicationOptions {
+        self.commands.push(UserCommand::new(name, code));
+        self
+    }
+
+    /// Runs the given number of instances of the application, E.g. the consumers of a queue.
+    /// Each instance gets its replica index from `RunContext::replica`.
+    pub fn replicas(mut self, replicas: usize) -> ApplicationOptions {
+        self.replicas = replicas;
+        self
+    }
+
+    /// Maps two user-defined control codes to adding and removing a replica of the
+    /// application, E.g. `sc control <service> 140` starts one more replica.
+    pub fn scale_commands(mut self, up: u8, down: u8) -> ApplicationOptions {
+        self.scale_commands = Some(ScaleCommands { up, down });
+        self
+    }
+
+    /// Expects the application to call `RunContext::heartbeat` at least once per timeout while
+    /// it runs. Otherwise it is reported as hung, and the host acts as `on_missed` tells.
+    pub fn heartbeat(mut self, timeout: Duration, on_missed: MissedHeartbeat) -> ApplicationOptions {
+        self.heartbeat = Some(HeartbeatPolicy { timeout, on_missed });
+        self
+    }
+}
+
+// A registered application. The factory is kept for the whole life of the service, since the
+// host calls it again to get a fresh instance whenever the application is restarted.
+#[derive(Clone)]
+pub struct RegisteredApplication {
+    factory: RegisteredFactory,
+    options: ApplicationOptions,
+    configuration_check: Option<ConfigurationCheck>,
+}
+
+impl RegisteredApplication {
+    fn new(factory: RegisteredFactory, options: ApplicationOptions) -> RegisteredApplication {
+        RegisteredApplication { factory, options, configuration_check: None }
+    }
+
+    pub fn factory(&self) -> &RegisteredFactory {
+        &self.factory
+    }
+
+    pub fn is_async(&self) -> bool {
+        matches!(self.factory, RegisteredFactory::Async(_) | RegisteredFactory::ConfiguredAsync(_))
+    }
+
+    pub fn options(&self) -> &ApplicationOptions {
+        &self.options
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.options.name.as_deref()
+    }
+
+    // Checks the section of a configured application, E.g. before a reloaded configuration
+    // takes effect. The other applications accept any configuration.
+    pub(crate) fn check_configuration(&self, configuration: &Configuration) -> ServiceResult<()> {
+        self.configuration_check.as_ref().map_or(Ok(()), |check| { check(configuration) })
+    }
+
+    // How the application shows in the logs and errors, E.g. "Application 'cache'" or
+    // "Application #1" if it has no name.
+    pub(crate) fn identity(&self, index: usize) -> String {
+        match self.name() {
+            Some(name) => format!("Application '{}'", name),
+            None => format!("Application #{}", index),
+        }
+    }
+
+    // The first replica shows as the application itself, the others as E.g.
+    // "Application 'cache' (replica 2)".
+    pub(crate) fn replica_identity(&self, index: usize, replica: usize) -> String {
+        match replica {
+            0 => self.identity(index),
+            _ => format!("{} (replica {})", self.identity(index), replica),
+        }
+    }
+}
+
+// The registry holds the application factories of a service. It is owned by the service (rather
+// than being a global), so several registries can be constructed and run in the same process,
+// one after another. Cloning the registry is cheap: the factories are shared.
+#[derive(Clone, Default)]
+pub struct ApplicationRegistry {
+    applications: Vec<RegisteredApplication>,
+}
+
+impl ApplicationRegistry {
+    pub fn new() -> ApplicationRegistry {
+        ApplicationRegistry { applications: vec![] }
+    }
+
+    pub fn register<F>(&mut self, factory: F) -> &mut ApplicationRegistry
+        where F: Fn() -> Box<dyn SimpleApplication + Send + Sync> + Send + Sync + 'static {
+        self.register_boxed(Box::new(factory))
+    }
+
+    pub fn register_boxed(&mut self, factory: ApplicationFactory) -> &mut ApplicationRegistry {
+        let options = ApplicationOptions::default();
+        self.applications.push(RegisteredApplication::new(RegisteredFactory::Simple(Arc::new(factory)), options));
+        self
+    }
+
+    /// Fails if the name is already registered, or if the dependencies form a cycle. The
+    /// dependencies may be registered later, they are checked when the service runs.
+    pub fn register_with_options(&mut self, options: ApplicationOptions, factory: ApplicationFactory) -> ServiceResult<&mut ApplicationRegistry> {
+        self.push(RegisteredApplication::new(RegisteredFactory::Simple(Arc::new(factory)), options))
+    }
+
+    pub fn register_async<F>(&mut self, factory: F) -> &mut ApplicationRegistry
+        where F: Fn() -> Arc<dyn AsyncApplication> + Send + Sync + 'static {
+        let options = ApplicationOptions::default();
+        self.applications.push(RegisteredApplication::new(RegisteredFactory::Async(Arc::new(Box::new(factory))), options));
+        self
+    }
+
+    pub fn register_async_with_options(&mut self, options: ApplicationOptions, factory: AsyncApplicationFactory) -> ServiceResult<&mut ApplicationRegistry> {
+        self.push(RegisteredApplication::new(RegisteredFactory::Async(Arc::new(factory)), options))
+    }
+
+    /// Registers an application which is created from its section of the configuration, the
+    /// section named after the application. The section is deserialized each time the
+    /// application is created, so an invalid section fails the start of the service. Please
+    /// refer to the `configuration` module.
+    pub fn register_configured<C, F>(&mut self, options: ApplicationOptions, factory: F) -> ServiceResult<&mut ApplicationRegistry>
+        where C: DeserializeOwned, F: Fn(C) -> Box<dyn SimpleApplication + Send + Sync> + Send + Sync + 'static {
+        let section = section_name(&options)?;
+        let check = configuration_check::<C>(section.clone());
+        let factory: ConfiguredApplicationFactory = Box::new(move |configuration| {
+            configuration.section(&section).map(&factory)
+        });
+        let application = RegisteredApplication::new(RegisteredFactory::ConfiguredSimple(Arc::new(factory)), options);
+        self.push(RegisteredApplication { configuration_check: Some(check), ..application })
+    }
+
+    pub fn register_async_configured<C, F>(&mut self, options: ApplicationOptions, factory: F) -> ServiceResult<&mut ApplicationRegistry>
+        where C: DeserializeOwned, F: Fn(C) -> Arc<dyn AsyncApplication> + Send + Sync + 'static {
+        let section = section_name(&options)?;
+        let check = configuration_check::<C>(section.clone());
+        let factory: ConfiguredAsyncApplicationFactory = Box::new(move |configuration| {
+            configuration.section(&section).map(&factory)
+        });
+        let application = RegisteredApplication::new(RegisteredFactory::ConfiguredAsync(Arc::new(factory)), options);
+        self.push(RegisteredApplication { configuration_check: Some(check), ..application })
+    }
+
+    pub fn append(&mut self, other: ApplicationRegistry) -> ServiceResult<&mut ApplicationRegistry> {
+        for application in other.applications {
+            self.push(application)?;
+        }
+        Ok(self)
+    }
+
+    fn push(&mut self, application: RegisteredApplication) -> ServiceResult<&mut ApplicationRegistry> {
+        if let Some(name) = application.name() {
+            if self.applications.iter().any(|registered| registered.name() == Some(name)) {
+                return Err(ServiceError::of_kind(ErrorKind::Config, format!("Application '{}' is already registered. ", name)));
+            }
+            // The section of that name gives the number of replicas of the applications.
+            if Configuration::is_reserved_section(name) {
+                return Err(ServiceError::of_kind(ErrorKind::Config, format!("The application name '{}' is reserved. ", name)));
+            }
+        }
+        if application.options.replicas == 0 {
+            return Err(ServiceError::of_kind(ErrorKind::Config, format!("{} must have at least one replica. ", application.identity(self.applications.len()))));
+        }
+        if application.options.heartbeat.map(|heartbeat| heartbeat.timeout.is_zero()).unwrap_or(false) {
+            return Err(ServiceError::of_kind(ErrorKind::Config, format!("The heartbeat timeout of {} must not be zero. ", application.identity(self.applications.len()))));
+        }
+        command::check_commands(&self.applications, &application)?;
+        self.applications.push(application);
+        dependency::check_cycles(&self.applications).inspect_err(|_| { self.applications.pop(); })?;
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.applications.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.applications.is_empty()
+    }
+
+    pub fn applications(&self) -> &[RegisteredApplication] {
+        &self.applications
+    }
+
+    pub fn has_async_applications(&self) -> bool {
+        self.applications.iter().any(|application| application.is_async())
+    }
+}
+
+// The section of a configured application is named after the application.
+fn section_name(options: &ApplicationOptions) -> ServiceResult<String> {
+    options.name.clone()
+        .ok_or_else(|| { ServiceError::of_kind(ErrorKind::Config, "A configured application must have a name, which names its configuration section. ") })
+}
+
+fn configuration_check<C: DeserializeOwned>(section: String) -> ConfigurationCheck {
+    Arc::new(move |configuration| { configuration.section::<C>(&section).map(|_| ()) })
+}
+
+impl From<Vec<ApplicationFactory>> for ApplicationRegistry {
+    fn from(factories: Vec<ApplicationFactory>) -> Self {
+        let mut registry = ApplicationRegistry::new();
+        factories.into_iter().for_each(|factory| { registry.register_boxed(factory); });
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::application::SimpleApplication;
+    use crate::error::{ErrorKind, ServiceError, ServiceResult};
+    use super::{ApplicationOptions, ApplicationRegistry};
+
+    struct IdleApplication {}
+
+    impl SimpleApplication for IdleApplication {
+        fn handle_error(&self, _error: &ServiceError) {}
+    }
+
+    fn register(registry: &mut ApplicationRegistry, options: ApplicationOptions) -> ServiceResult<()> {
+        registry.register_with_options(options, Box::new(|| { Box::new(IdleApplication {}) })).map(|_| ())
+    }
+
+    #[test]
+    fn rejects_a_cycle() {
+        let mut registry = ApplicationRegistry::new();
+        register(&mut registry, ApplicationOptions::new().name("a").depends_on("b")).unwrap();
+        register(&mut registry, ApplicationOptions::new().name("b").depends_on("c")).unwrap();
+        let error = register(&mut registry, ApplicationOptions::new().name("c").depends_on("a")).unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::Config);
+        assert!(error.to_string().contains("a -> b -> c -> a"), "{}", error);
+        // The application closing the cycle is not registered.
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn rejects_duplicate_and_reserved_names() {
+        let mut registry = ApplicationRegistry::new();
+        register(&mut registry, ApplicationOptions::new().name("a")).unwrap();
+
+        assert_eq!(register(&mut registry, ApplicationOptions::new().name("a")).unwrap_err().kind(), ErrorKind::Config);
+        assert_eq!(register(&mut registry, ApplicationOptions::new().name("replicas")).unwrap_err().kind(), ErrorKind::Config);
+        assert_eq!(register(&mut registry, ApplicationOptions::new().name("b").replicas(0)).unwrap_err().kind(), ErrorKind::Config);
+        assert_eq!(registry.len(), 1);
+    }
+}
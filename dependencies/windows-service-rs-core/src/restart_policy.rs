@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::random::random_unit;
+
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+const DEFAULT_JITTER: f64 = 0.1;
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+const DEFAULT_RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+/// When the host restarts an application whose `run` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartMode {
+    /// The application is never restarted.
+    Never,
+    /// The application is restarted if `run` returns an error.
+    OnFailure,
+    /// The application is restarted whenever `run` returns, unless the service is stopping.
+    Always,
+}
+
+/// What the host does if an application is restarted too often.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalation {
+    /// Stops the whole service with `exit_code::application_failure` of the application
+    /// (`APPLICATION_FAILURE` plus its index), or with the exit code its error sets itself.
+    StopService,
+    /// Leaves the application failed, the other applications keep running.
+    LeaveFailed,
+}
+
+// The restart policy of an application. The delay before each restart grows exponentially from
+// the initial backoff up to the max backoff, with a random jitter so that applications failing
+// for the same reason (E.g. a database outage) are not restarted at the same time. If the
+// application is restarted more than `max_restarts` times within `restart_window`, the host
+// gives up and escalates. For example:
+//
+// RestartPolicy::on_failure()
+//     .backoff(Duration::from_secs(1), Duration::from_secs(30))
+//     .max_restarts(3, Duration::from_secs(60))
+//     .escalation(Escalation::StopService)
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    pub mode: RestartMode,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// The delay is randomized by +/- this fraction of it, E.g. 0.1 for +/- 10%. It is clamped to
+    /// [0, 1], a NaN is no jitter.
+    pub jitter: f64,
+    pub max_restarts: u32,
+    pub restart_window: Duration,
+    pub escalation: Escalation,
+}
+
+impl RestartPolicy {
+    pub fn new(mode: RestartMode) -> RestartPolicy {
+        RestartPolicy {
+            mode,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            jitter: DEFAULT_JITTER,
+            max_restarts: DEFAULT_MAX_RESTARTS,
+            restart_window: DEFAULT_RESTART_WINDOW,
+            escalation: Escalation::LeaveFailed,
+        }
+    }
+
+    pub fn never() -> RestartPolicy {
+        RestartPolicy::new(RestartMode::Never)
+    }
+
+    pub fn on_failure() -> RestartPolicy {
+        RestartPolicy::new(RestartMode::OnFailure)
+    }
+
+    pub fn always() -> RestartPolicy {
+        RestartPolicy::new(RestartMode::Always)
+    }
+
+    pub fn backoff(mut self, initial_backoff: Duration, max_backoff: Duration) -> RestartPolicy {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// The jitter is clamped to [0, 1], a NaN is no jitter.
+    pub fn jitter(mut self, jitter: f64) -> RestartPolicy {
+        self.jitter = clamp_jitter(jitter);
+        self
+    }
+
+    pub fn max_restarts(mut self, max_restarts: u32, restart_window: Duration) -> RestartPolicy {
+        self.max_restarts = max_restarts;
+        self.restart_window = restart_window;
+        self
+    }
+
+    pub fn escalation(mut self, escalation: Escalation) -> RestartPolicy {
+        self.escalation = escalation;
+        self
+    }
+
+    fn should_restart(&self, failed: bool) -> bool {
+        match self.mode {
+            RestartMode::Never => false,
+            RestartMode::OnFailure => failed,
+            RestartMode::Always => true,
+        }
+    }
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::never()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RestartDecision {
+    // The application is done, it is not restarted.
+    Finish,
+    // Restart the application after the delay.
+    Restart(Duration),
+    // The application is restarted too often.
+    Escalate(Escalation),
+}
+
+// Tracks the restarts of one application.
+pub(crate) struct RestartTracker {
+    policy: RestartPolicy,
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartTracker {
+    pub(crate) fn new(policy: RestartPolicy) -> RestartTracker {
+        RestartTracker { policy, restarts: VecDeque::new() }
+    }
+
+    pub(crate) fn decide(&mut self, failed: bool) -> RestartDecision {
+        if !self.policy.should_restart(failed) {
+            return RestartDecision::Finish;
+        }
+
+        let now = Instant::now();
+        while self.restarts.front().is_some_and(|restart| now.duration_since(*restart) > self.policy.restart_window) {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.policy.max_restarts as usize {
+            return RestartDecision::Escalate(self.policy.escalation);
+        }
+
+        // The more restarts in the window, the longer the delay.
+        let exponent = self.restarts.len().min(31) as i32;
+        let backoff = self.policy.initial_backoff.as_secs_f64() * 2f64.powi(exponent);
+        let backoff = backoff.min(self.policy.max_backoff.as_secs_f64());
+        // The jitter is public, so it is clamped here again. The delay falls back to the backoff
+        // if it isn't a valid duration anyway, E.g. with a huge max backoff.
+        let jitter = backoff * clamp_jitter(self.policy.jitter) * (random_unit() * 2.0 - 1.0);
+        let delay = Duration::try_from_secs_f64((backoff + jitter).max(0.0))
+            .or_else(|_| Duration::try_from_secs_f64(backoff))
+            .unwrap_or(self.policy.max_backoff);
+        self.restarts.push_back(now);
+        RestartDecision::Restart(delay)
+    }
+}
+
+fn clamp_jitter(jitter: f64) -> f64 {
+    if jitter.is_nan() { 0.0 } else { jitter.clamp(0.0, 1.0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use super::{Escalation, RestartDecision, RestartPolicy, RestartTracker};
+
+    fn delays(tracker: &mut RestartTracker, count: usize) -> Vec<Duration> {
+        (0..count).map(|_| match tracker.decide(true) {
+            RestartDecision::Restart(delay) => delay,
+            decision => panic!("Unexpected decision {:?}.", decision),
+        }).collect()
+    }
+
+    #[test]
+    fn restarts_by_mode() {
+        assert_eq!(RestartTracker::new(RestartPolicy::never()).decide(true), RestartDecision::Finish);
+        assert_eq!(RestartTracker::new(RestartPolicy::on_failure()).decide(false), RestartDecision::Finish);
+        assert!(matches!(RestartTracker::new(RestartPolicy::on_failure()).decide(true), RestartDecision::Restart(_)));
+        assert!(matches!(RestartTracker::new(RestartPolicy::always()).decide(false), RestartDecision::Restart(_)));
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_max() {
+        let mut tracker = RestartTracker::new(RestartPolicy::on_failure()
+            .backoff(Duration::from_secs(1), Duration::from_secs(5))
+            .jitter(0.0)
+            .max_restarts(10, Duration::from_secs(60)));
+        assert_eq!(delays(&mut tracker, 5), [1, 2, 4, 5, 5].iter().map(|secs| Duration::from_secs(*secs)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn jitter_stays_within_its_bound() {
+        let mut tracker = RestartTracker::new(RestartPolicy::on_failure()
+            .backoff(Duration::from_secs(10), Duration::from_secs(10))
+            .jitter(0.5)
+            .max_restarts(100, Duration::from_secs(60)));
+        for delay in delays(&mut tracker, 100) {
+            assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(15), "Delay {:?} is out of bound.", delay);
+        }
+
+        assert_eq!(RestartPolicy::on_failure().jitter(2.0).jitter, 1.0);
+        assert_eq!(RestartPolicy::on_failure().jitter(-1.0).jitter, 0.0);
+        assert_eq!(RestartPolicy::on_failure().jitter(f64::NAN).jitter, 0.0);
+        // The delay is still a valid duration with the largest backoff.
+        let mut tracker = RestartTracker::new(RestartPolicy::on_failure().backoff(Duration::MAX, Duration::MAX).jitter(1.0));
+        delays(&mut tracker, 3);
+    }
+
+    #[test]
+    fn escalates_after_max_restarts_within_the_window() {
+        let mut tracker = RestartTracker::new(RestartPolicy::on_failure()
+            .backoff(Duration::ZERO, Duration::ZERO)
+            .max_restarts(2, Duration::from_millis(100))
+            .escalation(Escalation::StopService));
+        delays(&mut tracker, 2);
+        assert_eq!(tracker.decide(true), RestartDecision::Escalate(Escalation::StopService));
+
+        // The restarts out of the window don't count any more.
+        thread::sleep(Duration::from_millis(150));
+        delays(&mut tracker, 2);
+        assert_eq!(tracker.decide(true), RestartDecision::Escalate(Escalation::StopService));
+    }
+}
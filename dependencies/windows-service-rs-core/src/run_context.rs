@@ -0,0 +1,159 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crate::configuration::{Configuration, SharedConfiguration};
+use crate::heartbeat::Heartbeat;
+use crate::message_bus::MessageBus;
+use crate::metrics::MetricsRegistry;
+use crate::pause_signal::PauseSignal;
+use crate::shutdown_token::ShutdownToken;
+
+/// Why the host asks the applications to exit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The service is stopped by an operator (E.g. `sc stop`, `systemctl stop` or Ctrl+C).
+    Operator,
+    /// The machine is shutting down.
+    Shutdown,
+    /// An application fails and its failure stops the service.
+    ApplicationFailure,
+}
+
+// Everything the host provides to a running application. Cloning the context is cheap, all the
+// clones share the same state.
+#[derive(Clone)]
+pub struct RunContext {
+    shutdown_token: ShutdownToken,
+    pause_signal: PauseSignal,
+    stop_reason: Arc<Mutex<Option<StopReason>>>,
+    failures: Arc<AtomicU32>,
+    metrics: MetricsRegistry,
+    configuration: SharedConfiguration,
+    message_bus: MessageBus,
+    replica: usize,
+    heartbeat: Heartbeat,
+}
+
+impl RunContext {
+    /// Creates a context from a bare exit signal. Please refer to
+    /// `ShutdownToken::from_exit_signal`.
+    pub fn new(exit_signal: Arc<AtomicBool>, pause_signal: PauseSignal) -> RunContext {
+        RunContext::from_shutdown_token(ShutdownToken::from_exit_signal(exit_signal), pause_signal)
+    }
+
+    pub fn from_shutdown_token(shutdown_token: ShutdownToken, pause_signal: PauseSignal) -> RunContext {
+        RunContext {
+            message_bus: MessageBus::new().with_token(shutdown_token.clone()),
+            shutdown_token,
+            pause_signal,
+            stop_reason: Arc::new(Mutex::new(None)),
+            failures: Arc::new(AtomicU32::new(0)),
+            metrics: MetricsRegistry::new(),
+            configuration: Arc::new(RwLock::new(Arc::new(Configuration::new()))),
+            replica: 0,
+            heartbeat: Heartbeat::default(),
+        }
+    }
+
+    pub(crate) fn with_stop_reason(mut self, stop_reason: Arc<Mutex<Option<StopReason>>>) -> RunContext {
+        self.stop_reason = stop_reason;
+        self
+    }
+
+    pub(crate) fn with_metrics(mut self, metrics: MetricsRegistry) -> RunContext {
+        self.metrics = metrics;
+        self
+    }
+
+    pub(crate) fn with_configuration(mut self, configuration: SharedConfiguration) -> RunContext {
+        self.configuration = configuration;
+        self
+    }
+
+    pub(crate) fn with_message_bus(mut self, message_bus: MessageBus) -> RunContext {
+        self.message_bus = message_bus.with_token(self.shutdown_token.clone());
+        self
+    }
+
+    pub(crate) fn with_replica(mut self, replica: usize) -> RunContext {
+        self.replica = replica;
+        self
+    }
+
+    // A context which shares everything but the shutdown token.
+    pub(crate) fn with_shutdown_token(&self, shutdown_token: ShutdownToken) -> RunContext {
+        let message_bus = self.message_bus.with_token(shutdown_token.clone());
+        RunContext { shutdown_token, message_bus, ..self.clone() }
+    }
+
+    pub fn exit_signal(&self) -> Arc<AtomicBool> {
+        self.shutdown_token.exit_signal()
+    }
+
+    pub fn is_exit_requested(&self) -> bool {
+        self.shutdown_token.is_cancelled()
+    }
+
+    /// The token is cancelled when the application is requested to exit.
+    pub fn shutdown_token(&self) -> &ShutdownToken {
+        &self.shutdown_token
+    }
+
+    pub fn pause_signal(&self) -> &PauseSignal {
+        &self.pause_signal
+    }
+
+    /// Returns the reason of the stop once the exit signal is set.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        *self.stop_reason.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The metrics of the application, they are exposed with the `application` and `replica`
+    /// labels. Please refer to `MetricsRegistry`.
+    pub fn metrics(&self) -> &MetricsRegistry {
+        &self.metrics
+    }
+
+    /// The configuration of the service. A configured application gets its own section from
+    /// the factory, but it may read the other sections here. It is the reloaded configuration
+    /// once the configuration is reloaded.
+    pub fn configuration(&self) -> Arc<Configuration> {
+        self.configuration.read().map(|configuration| configuration.clone())
+            .unwrap_or_else(|e| { e.into_inner().clone() })
+    }
+
+    /// The message bus shared by the applications of the service. Please refer to
+    /// `MessageBus`.
+    pub fn message_bus(&self) -> &MessageBus {
+        &self.message_bus
+    }
+
+    /// The index of the replica of the application, from 0. Please refer to
+    /// `ApplicationOptions::replicas`.
+    pub fn replica(&self) -> usize {
+        self.replica
+    }
+
+    /// Tells the host that the application is alive, E.g. on each iteration of its main loop.
+    /// Please refer to `ApplicationOptions::heartbeat`.
+    pub fn heartbeat(&self) {
+        self.heartbeat.beat();
+    }
+
+    pub(crate) fn heartbeat_clock(&self) -> &Heartbeat {
+        &self.heartbeat
+    }
+
+    /// How many times the application has failed so far (returned an error or panicked). The
+    /// counter is kept across restarts.
+    pub fn failure_count(&self) -> u32 {
+        self.failures.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn record_failure(&self) -> u32 {
+        self.failures.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub(crate) fn failure_counter(&self) -> Arc<AtomicU32> {
+        self.failures.clone()
+    }
+}
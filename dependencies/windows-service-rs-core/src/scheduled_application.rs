@@ -0,0 +1,528 @@
+use std::collections::VecDeque;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use crate::application::SimpleApplication;
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::metrics::{Counter, Histogram, MetricsRegistry};
+use crate::panic_guard;
+use crate::run_context::RunContext;
+
+// The scheduler sleeps until the next run is due, but it wakes up at least this often, so that
+// a change of the system clock is noticed.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+// While a run is in progress, the scheduler checks this often whether it has finished, to start
+// the next queued run.
+const RUN_POLLING_INTERVAL: Duration = Duration::from_millis(100);
+// The missed runs are counted up to this limit, E.g. for a job scheduled every second while the
+// service was down for a week.
+const MAX_MISSED_RUNS: usize = 1000;
+// The buckets of the run duration, in seconds: a job runs for seconds to hours.
+const RUN_DURATION_BUCKETS: [f64; 10] = [0.1, 1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0];
+
+/// When a job runs: a cron expression in a time zone, or a fixed interval.
+#[derive(Clone)]
+pub struct JobSchedule {
+    kind: ScheduleKind,
+}
+
+#[derive(Clone)]
+enum ScheduleKind {
+    Cron { expression: String, schedule: Box<cron::Schedule>, time_zone: Tz },
+    Interval(Duration),
+}
+
+impl JobSchedule {
+    /// A cron expression in UTC, either the classic 5 fields ("minute hour day month weekday",
+    /// E.g. "30 2 * * Mon-Fri") or 6 to 7 fields starting with the seconds (and ending with the
+    /// year).
+    pub fn cron(expression: &str) -> ServiceResult<JobSchedule> {
+        let fields = expression.split_whitespace().count();
+        let full_expression = match fields {
+            5 => format!("0 {}", expression.trim()),
+            6 | 7 => String::from(expression.trim()),
+            _ => return Err(ServiceError::of_kind(ErrorKind::Config, format!(
+                "Invalid cron expression '{}', it has {} fields rather than 5 to 7. ", expression, fields))),
+        };
+        let schedule = cron::Schedule::from_str(&full_expression).map_err(|e| {
+            ServiceError::caused_by(e, &format!("Invalid cron expression '{}'. ", expression)).with_kind(ErrorKind::Config)
+        })?;
+        Ok(JobSchedule {
+            kind: ScheduleKind::Cron { expression: String::from(expression.trim()), schedule: Box::new(schedule), time_zone: Tz::UTC },
+        })
+    }
+
+    /// A run every interval. The first run is due one interval after the start.
+    pub fn every(interval: Duration) -> ServiceResult<JobSchedule> {
+        if interval.is_zero() {
+            return Err(ServiceError::of_kind(ErrorKind::Config, "The interval of a job schedule must not be zero. "));
+        }
+        Ok(JobSchedule { kind: ScheduleKind::Interval(interval) })
+    }
+
+    /// Evaluates the cron expression in the given IANA time zone, E.g. "Europe/Paris", rather
+    /// than in UTC. The daylight saving time changes follow the time zone: a run in the skipped
+    /// hour doesn't happen, a run in the repeated hour happens once. It doesn't apply to an
+    /// interval.
+    pub fn in_time_zone(mut self, time_zone: &str) -> ServiceResult<JobSchedule> {
+        let parsed = Tz::from_str(time_zone).map_err(|e| {
+            ServiceError::of_kind(ErrorKind::Config, format!("Invalid time zone '{}': {}. ", time_zone, e))
+        })?;
+        if let ScheduleKind::Cron { time_zone, .. } = &mut self.kind {
+            *time_zone = parsed;
+        }
+        Ok(self)
+    }
+
+    /// The first run time strictly after the given time, None if the schedule has no more
+    /// runs (E.g. a cron expression limited to a past year).
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match &self.kind {
+            ScheduleKind::Cron { schedule, time_zone, .. } => {
+                schedule.after(&after.with_timezone(time_zone)).next().map(|next| next.with_timezone(&Utc))
+            },
+            ScheduleKind::Interval(interval) => {
+                chrono::Duration::from_std(*interval).ok().and_then(|interval| after.checked_add_signed(interval))
+            },
+        }
+    }
+}
+
+impl fmt::Display for JobSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ScheduleKind::Cron { expression, time_zone, .. } => write!(f, "cron '{}' ({})", expression, time_zone),
+            ScheduleKind::Interval(interval) => write!(f, "every {:?}", interval),
+        }
+    }
+}
+
+/// What the scheduler does when a run is due while the previous run is still in progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlap {
+    /// The due run is skipped.
+    Skip,
+    /// The due run starts once the runs queued before it have finished.
+    Queue,
+    /// The due run starts at once, alongside the previous one.
+    Concurrent,
+}
+
+/// What the scheduler does at the start with the runs which were due while the service was
+/// down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedRuns {
+    /// The job runs once at the start, however many runs were missed.
+    RunOnce,
+    /// The missed runs are skipped, the job runs at the next scheduled time.
+    Skip,
+}
+
+/// A run of a scheduled job.
+#[derive(Debug, Clone)]
+pub struct JobRun {
+    /// The number of the run since the application has started, from 1.
+    pub number: u64,
+    /// The time the run was due, which is earlier than now if it was queued or missed.
+    pub scheduled_at: DateTime<Utc>,
+}
+
+/// The body of a scheduled job. A closure `Fn(&JobRun, &RunContext) -> ServiceResult<()>`
+/// is a job.
+pub trait ScheduledJob: Send + Sync {
+    /// Runs the job once. It is called on a thread of its own, and it should return early when
+    /// the shutdown token of the context is cancelled.
+    fn run(&self, run: &JobRun, context: &RunContext) -> ServiceResult<()>;
+
+    /// Gets the error of a failed run, which is logged by the scheduler already. A failed run
+    /// doesn't stop the application, the job runs again at the next scheduled time.
+    fn handle_error(&self, _error: &ServiceError) {}
+}
+
+impl<F> ScheduledJob for F where F: Fn(&JobRun, &RunContext) -> ServiceResult<()> + Send + Sync {
+    fn run(&self, run: &JobRun, context: &RunContext) -> ServiceResult<()> {
+        self(run, context)
+    }
+}
+
+// The scheduled application adapts a job to an application: it sleeps until the next run is
+// due, runs the job and logs the duration and the outcome of every run. For example:
+//
+// let report = ScheduledApplication::new("nightly-report", JobSchedule::cron("30 2 * * *")?
+//         .in_time_zone("Europe/Paris")?, |run: &JobRun, context: &RunContext| { build_report(run, context) })
+//     .overlap(Overlap::Skip)
+//     .missed_runs(MissedRuns::RunOnce);
+// builder.scheduled_application(ApplicationOptions::new(), report)
+//
+// The runs happen on threads of their own, so that the scheduler sees a run due while the
+// previous one is still in progress, and applies the overlap policy. When the service stops,
+// the scheduler wakes up at once: the queued runs are dropped, and the runs in progress get the
+// cancelled shutdown token and are waited for.
+//
+// The time of the last run is saved to the state file, so that the scheduler knows at the next
+// start which runs were missed while the service was down. The state file is
+// "<temp dir>/<name>.last-run" unless `state_file` gives another one, E.g. in the data
+// directory of the service, since the temp directory may be cleaned.
+#[derive(Clone)]
+pub struct ScheduledApplication {
+    name: String,
+    schedule: JobSchedule,
+    job: Arc<dyn ScheduledJob>,
+    overlap: Overlap,
+    missed_runs: MissedRuns,
+    state_file: Option<PathBuf>,
+}
+
+impl ScheduledApplication {
+    /// A job which skips the overlapping runs and the missed runs.
+    pub fn new<N, J>(name: N, schedule: JobSchedule, job: J) -> ScheduledApplication
+        where N: Into<String>, J: ScheduledJob + 'static {
+        ScheduledApplication {
+            name: name.into(),
+            schedule,
+            job: Arc::new(job),
+            overlap: Overlap::Skip,
+            missed_runs: MissedRuns::Skip,
+            state_file: None,
+        }
+    }
+
+    pub fn overlap(mut self, overlap: Overlap) -> ScheduledApplication {
+        self.overlap = overlap;
+        self
+    }
+
+    pub fn missed_runs(mut self, missed_runs: MissedRuns) -> ScheduledApplication {
+        self.missed_runs = missed_runs;
+        self
+    }
+
+    /// The file which keeps the time of the last run across the restarts of the service.
+    pub fn state_file<P: Into<PathBuf>>(mut self, state_file: P) -> ScheduledApplication {
+        self.state_file = Some(state_file.into());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn schedule(&self) -> &JobSchedule {
+        &self.schedule
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.state_file.clone().unwrap_or_else(|| {
+            let file_name: String = self.name.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+                .collect();
+            env::temp_dir().join(format!("{}.last-run", file_name))
+        })
+    }
+
+    fn load_last_run(&self) -> Option<DateTime<Utc>> {
+        let text = fs::read_to_string(self.state_path()).ok()?;
+        DateTime::parse_from_rfc3339(text.trim()).ok().map(|last_run| last_run.with_timezone(&Utc))
+    }
+
+    // A failure to save is logged only: at worst, the missed runs are not detected at the next
+    // start.
+    fn save_last_run(&self, scheduled_at: DateTime<Utc>) {
+        let path = self.state_path();
+        fs::write(&path, scheduled_at.to_rfc3339()).unwrap_or_else(|e| {
+            log::warn!("Job '{}' fails to save the last run time to {}: {}", self.name, path.display(), e);
+        });
+    }
+
+    // The first due time: now if a missed run has to be caught up, the next scheduled time
+    // otherwise.
+    fn first_run(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let last_run = match self.load_last_run() {
+            Some(last_run) => last_run,
+            None => return self.schedule.next_after(now),
+        };
+        let missed = std::iter::successors(self.schedule.next_after(last_run), |at| self.schedule.next_after(*at))
+            .take_while(|at| *at <= now)
+            .take(MAX_MISSED_RUNS)
+            .count();
+        match (missed, self.missed_runs) {
+            (0, _) => self.schedule.next_after(now),
+            (_, MissedRuns::RunOnce) => {
+                log::info!("Job '{}' has missed {} run(s) since {}, run it once now.", self.name, missed, last_run);
+                Some(now)
+            },
+            (_, MissedRuns::Skip) => {
+                log::info!("Job '{}' has missed {} run(s) since {}, skip them.", self.name, missed, last_run);
+                self.schedule.next_after(now)
+            },
+        }
+    }
+}
+
+impl SimpleApplication for ScheduledApplication {
+    fn handle_error(&self, error: &ServiceError) {
+        self.job.handle_error(error);
+    }
+
+    fn run_with_context(&self, context: &RunContext) -> ServiceResult<()> {
+        let mut scheduler = Scheduler::new(self, context);
+        let mut next = self.first_run(Utc::now());
+        log::info!("Job '{}' is scheduled {}, next run at {}.", self.name, self.schedule, describe(next));
+        loop {
+            scheduler.reap();
+            if self.overlap == Overlap::Queue && scheduler.active.is_empty() {
+                if let Some(scheduled_at) = scheduler.queued.pop_front() {
+                    scheduler.start(scheduled_at);
+                }
+            }
+            let now = Utc::now();
+            match next {
+                Some(due) if due <= now => {
+                    // If the scheduler was late (E.g. the machine was suspended), the runs due
+                    // in the meantime are not caught up, the next one is after now.
+                    next = self.schedule.next_after(now);
+                    scheduler.on_due(due);
+                    continue;
+                },
+                _ => {},
+            }
+            let mut sleep = next.map(|due| (due - now).to_std().unwrap_or_default()).unwrap_or(MAX_SLEEP).min(MAX_SLEEP);
+            if !scheduler.active.is_empty() || !scheduler.queued.is_empty() {
+                sleep = sleep.min(RUN_POLLING_INTERVAL);
+            }
+            if !context.shutdown_token().sleep_or_cancel(sleep) {
+                break;
+            }
+        }
+        scheduler.stop();
+        Ok(())
+    }
+}
+
+fn describe(time: Option<DateTime<Utc>>) -> String {
+    time.map(|time| time.to_rfc3339()).unwrap_or_else(|| String::from("<never>"))
+}
+
+struct Scheduler<'a> {
+    application: &'a ScheduledApplication,
+    context: &'a RunContext,
+    runs: u64,
+    active: Vec<JoinHandle<()>>,
+    queued: VecDeque<DateTime<Utc>>,
+    metrics: RunMetrics,
+}
+
+#[derive(Clone)]
+struct RunMetrics {
+    succeeded: Counter,
+    failed: Counter,
+    skipped: Counter,
+    duration: Histogram,
+}
+
+impl RunMetrics {
+    fn new(metrics: &MetricsRegistry) -> RunMetrics {
+        let outcome = |outcome: &str| {
+            metrics.with_label("outcome", outcome).counter("service_job_runs_total", "The number of runs of the scheduled job, by outcome.")
+        };
+        RunMetrics {
+            succeeded: outcome("succeeded"),
+            failed: outcome("failed"),
+            skipped: outcome("skipped"),
+            duration: metrics.histogram_with_buckets(
+                "service_job_run_duration_seconds", "The duration of the runs of the scheduled job.", &RUN_DURATION_BUCKETS),
+        }
+    }
+}
+
+impl<'a> Scheduler<'a> {
+    fn new(application: &'a ScheduledApplication, context: &'a RunContext) -> Scheduler<'a> {
+        Scheduler {
+            application,
+            context,
+            runs: 0,
+            active: Vec::new(),
+            queued: VecDeque::new(),
+            metrics: RunMetrics::new(context.metrics()),
+        }
+    }
+
+    fn on_due(&mut self, scheduled_at: DateTime<Utc>) {
+        let name = &self.application.name;
+        if self.active.is_empty() || self.application.overlap == Overlap::Concurrent {
+            self.start(scheduled_at);
+            return;
+        }
+        match self.application.overlap {
+            Overlap::Queue => {
+                self.queued.push_back(scheduled_at);
+                log::info!("Job '{}' is still running, queue the run due at {} ({} queued).", name, scheduled_at, self.queued.len());
+            },
+            _ => {
+                self.metrics.skipped.inc();
+                log::warn!("Job '{}' is still running, skip the run due at {}.", name, scheduled_at);
+            },
+        }
+    }
+
+    fn start(&mut self, scheduled_at: DateTime<Utc>) {
+        self.runs += 1;
+        self.application.save_last_run(scheduled_at);
+        let run = JobRun { number: self.runs, scheduled_at };
+        let name = self.application.name.clone();
+        let job = self.application.job.clone();
+        let context = self.context.clone();
+        let metrics = self.metrics.clone();
+        self.active.push(thread::spawn(move || {
+            let identity = format!("Job '{}' run #{}", name, run.number);
+            log::info!("{} (due at {}) starts.", identity, run.scheduled_at);
+            let started = Instant::now();
+            let result = panic_guard::call(&identity, || { job.run(&run, &context) });
+            let elapsed = started.elapsed();
+            metrics.duration.observe(elapsed.as_secs_f64());
+            match result {
+                Ok(()) => {
+                    metrics.succeeded.inc();
+                    log::info!("{} succeeds in {:?}.", identity, elapsed);
+                },
+                Err(e) => {
+                    metrics.failed.inc();
+                    log::error!("{} fails in {:?}: {}", identity, elapsed, e);
+                    job.handle_error(&e);
+                },
+            }
+        }));
+    }
+
+    fn reap(&mut self) {
+        let (finished, active): (Vec<_>, Vec<_>) = self.active.drain(..).partition(|run| run.is_finished());
+        self.active = active;
+        for run in finished {
+            run.join().unwrap_or_else(|e| {
+                log::error!("Job '{}' run error: {:?}", self.application.name, e);
+            });
+        }
+    }
+
+    // The runs in progress see the cancelled shutdown token, the stop deadline of the host
+    // applies to them like to any application.
+    fn stop(mut self) {
+        if !self.queued.is_empty() {
+            log::warn!("Job '{}' stops, {} queued run(s) are dropped.", self.application.name, self.queued.len());
+        }
+        if !self.active.is_empty() {
+            log::info!("Job '{}' stops, wait for {} run(s) in progress.", self.application.name, self.active.len());
+        }
+        for run in std::mem::take(&mut self.active) {
+            run.join().unwrap_or_else(|e| {
+                log::error!("Job '{}' run error: {:?}", self.application.name, e);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use chrono::{DateTime, TimeZone, Utc};
+    use crate::error::{ErrorKind, ServiceResult};
+    use crate::pause_signal::PauseSignal;
+    use crate::run_context::RunContext;
+    use super::{JobRun, JobSchedule, MissedRuns, Overlap, ScheduledApplication, Scheduler};
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 18, hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn parses_the_cron_forms() {
+        // The classic form runs at second 0.
+        let schedule = JobSchedule::cron(" 30 2 * * * ").unwrap();
+        assert_eq!(schedule.next_after(at(0, 0, 0)), Some(at(2, 30, 0)));
+        assert_eq!(schedule.to_string(), "cron '30 2 * * *' (UTC)");
+
+        let schedule = JobSchedule::cron("15 30 2 * * *").unwrap();
+        assert_eq!(schedule.next_after(at(0, 0, 0)), Some(at(2, 30, 15)));
+
+        assert_eq!(JobSchedule::cron("30 2 * *").err().unwrap().kind(), ErrorKind::Config);
+        assert_eq!(JobSchedule::cron("61 2 * * *").err().unwrap().kind(), ErrorKind::Config);
+    }
+
+    fn with_last_run(name: &str, missed_runs: MissedRuns, last_run: Option<DateTime<Utc>>) -> ScheduledApplication {
+        let state_file = env::temp_dir().join(format!("scheduled-application-test-{}-{}.last-run", name, std::process::id()));
+        match last_run {
+            Some(last_run) => fs::write(&state_file, last_run.to_rfc3339()).unwrap(),
+            None => fs::remove_file(&state_file).unwrap_or_default(),
+        }
+        let job = |_run: &JobRun, _context: &RunContext| -> ServiceResult<()> { Ok(()) };
+        ScheduledApplication::new(name, JobSchedule::every(Duration::from_secs(3600)).unwrap(), job)
+            .missed_runs(missed_runs)
+            .state_file(state_file)
+    }
+
+    #[test]
+    fn computes_the_missed_runs_from_the_state_file() {
+        let now = at(12, 0, 0);
+        let next = Some(at(13, 0, 0));
+
+        let application = with_last_run("run-once", MissedRuns::RunOnce, Some(at(9, 30, 0)));
+        assert_eq!(application.first_run(now), Some(now));
+        let application = with_last_run("skip", MissedRuns::Skip, Some(at(9, 30, 0)));
+        assert_eq!(application.first_run(now), next);
+        // The run due at 12:00 is not missed yet.
+        let application = with_last_run("none-missed", MissedRuns::RunOnce, Some(at(11, 30, 0)));
+        assert_eq!(application.first_run(now), next);
+        let application = with_last_run("first-start", MissedRuns::RunOnce, None);
+        assert_eq!(application.first_run(now), next);
+
+        // Without a last run, the state file is removed.
+        for name in ["run-once", "skip", "none-missed"] {
+            with_last_run(name, MissedRuns::Skip, None);
+        }
+    }
+
+    #[test]
+    fn applies_the_overlap_policy() {
+        let release = Arc::new(AtomicBool::new(false));
+        for overlap in [Overlap::Skip, Overlap::Queue, Overlap::Concurrent] {
+            // The metrics of each policy start from zero.
+            let context = RunContext::new(Arc::new(AtomicBool::new(false)), PauseSignal::new());
+            let job_release = release.clone();
+            let job = move |_run: &JobRun, _context: &RunContext| -> ServiceResult<()> {
+                while !job_release.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Ok(())
+            };
+            let application = ScheduledApplication::new("overlap", JobSchedule::every(Duration::from_secs(60)).unwrap(), job)
+                .overlap(overlap)
+                .state_file(env::temp_dir().join(format!("scheduled-application-test-overlap-{}.last-run", std::process::id())));
+            release.store(false, Ordering::SeqCst);
+            let mut scheduler = Scheduler::new(&application, &context);
+            scheduler.on_due(at(12, 0, 0));
+            scheduler.on_due(at(12, 1, 0));
+
+            let expected = match overlap {
+                Overlap::Skip => (1, 0, 1),
+                Overlap::Queue => (1, 1, 0),
+                Overlap::Concurrent => (2, 0, 0),
+            };
+            assert_eq!((scheduler.active.len(), scheduler.queued.len(), scheduler.metrics.skipped.get()), expected, "{:?}", overlap);
+            release.store(true, Ordering::SeqCst);
+            fs::remove_file(application.state_path()).unwrap_or_default();
+            scheduler.stop();
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use crate::application::SimpleApplication;
+use crate::async_application::AsyncApplication;
+use crate::error::{ServiceError, ServiceResult};
+use crate::host::{self, HostBackend, RunMode};
+use crate::registry::{ApplicationOptions, ApplicationRegistry};
+use crate::scheduled_application::ScheduledApplication;
+use crate::service_config::{AcceptedControls, ServiceConfig, ServiceType};
+use crate::service_wrapper;
+
+// The service builder collects the service identity and the application factories, and then
+// runs the applications with the host backend. For example:
+//
+// ServiceBuilder::new()
+//     .service_name("sample-rust-service")
+//     .stop_wait_hint(Duration::from_secs(30))
+//     .application(move || { Box::new(WorkerApplicationOne::new(settings.clone())) })
+//     .run()
+//
+// A registration error (E.g. a dependency cycle) doesn't break the chain of calls, it is
+// returned by `run` before anything runs.
+pub struct ServiceBuilder {
+    config: ServiceConfig,
+    registry: ApplicationRegistry,
+    backend: Option<Box<dyn HostBackend>>,
+    registration_error: Option<ServiceError>,
+}
+
+impl ServiceBuilder {
+    pub fn new() -> ServiceBuilder {
+        ServiceBuilder::from_config(ServiceConfig::default())
+    }
+
+    pub fn from_config(config: ServiceConfig) -> ServiceBuilder {
+        ServiceBuilder { config, registry: ApplicationRegistry::new(), backend: None, registration_error: None }
+    }
+
+    pub fn service_name<N: Into<String>>(mut self, service_name: N) -> ServiceBuilder {
+        self.config.service_name = service_name.into();
+        self
+    }
+
+    pub fn service_type(mut self, service_type: ServiceType) -> ServiceBuilder {
+        self.config.service_type = service_type;
+        self
+    }
+
+    pub fn accepted_controls(mut self, accepted_controls: AcceptedControls) -> ServiceBuilder {
+        self.config.accepted_controls = accepted_controls;
+        self
+    }
+
+    pub fn start_wait_hint(mut self, start_wait_hint: Duration) -> ServiceBuilder {
+        self.config.start_wait_hint = start_wait_hint;
+        self
+    }
+
+    pub fn start_timeout(mut self, start_timeout: Duration) -> ServiceBuilder {
+        self.config.start_timeout = start_timeout;
+        self
+    }
+
+    pub fn stop_wait_hint(mut self, stop_wait_hint: Duration) -> ServiceBuilder {
+        self.config.stop_wait_hint = stop_wait_hint;
+        self
+    }
+
+    pub fn stop_timeout(mut self, stop_timeout: Duration) -> ServiceBuilder {
+        self.config.stop_timeout = stop_timeout;
+        self
+    }
+
+    pub fn shutdown_grace_period(mut self, shutdown_grace_period: Duration) -> ServiceBuilder {
+        self.config.shutdown_grace_period = shutdown_grace_period;
+        self
+    }
+
+    /// The number of worker threads of the Tokio runtime which runs the async applications.
+    pub fn async_worker_threads(mut self, async_worker_threads: usize) -> ServiceBuilder {
+        self.config.async_worker_threads = Some(async_worker_threads);
+        self
+    }
+
+    /// Serves the health endpoints on the given localhost port.
+    pub fn health_port(mut self, health_port: u16) -> ServiceBuilder {
+        self.config.health_port = Some(health_port);
+        self
+    }
+
+    /// Serves the metrics on the given localhost port, which may be the health port.
+    pub fn metrics_port(mut self, metrics_port: u16) -> ServiceBuilder {
+        self.config.metrics_port = Some(metrics_port);
+        self
+    }
+
+    pub fn health_check_interval(mut self, health_check_interval: Duration) -> ServiceBuilder {
+        self.config.health_check_interval = health_check_interval;
+        self
+    }
+
+    /// Reads the configuration of the applications from the given file rather than the one
+    /// given by the `--config` start argument or the SERVICE_CONFIG environment variable.
+    pub fn config_file<P: Into<PathBuf>>(mut self, config_file: P) -> ServiceBuilder {
+        self.config.config_file = Some(config_file.into());
+        self
+    }
+
+    /// The prefix of the environment overrides, `APP` by default.
+    pub fn config_environment_prefix<P: Into<String>>(mut self, prefix: P) -> ServiceBuilder {
+        self.config.config_environment_prefix = prefix.into();
+        self
+    }
+
+    /// Translates the signal to the user-defined control code on unix, E.g.
+    /// `user_control_signal(SIGUSR1, 130)`, so that `kill -USR1 <pid>` sends the command of
+    /// code 130. It is ignored on Windows, where the SCM sends the control codes.
+    pub fn user_control_signal(mut self, signal: i32, code: u8) -> ServiceBuilder {
+        self.config.user_control_signals.push((signal, code));
+        self
+    }
+
+    pub fn run_mode(mut self, run_mode: RunMode) -> ServiceBuilder {
+        self.config.run_mode = run_mode;
+        self
+    }
+
+    /// Uses the given backend rather than the one selected by the run mode.
+    pub fn backend(mut self, backend: Box<dyn HostBackend>) -> ServiceBuilder {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn application<F>(mut self, factory: F) -> ServiceBuilder
+        where F: Fn() -> Box<dyn SimpleApplication + Send + Sync> + Send + Sync + 'static {
+        self.registry.register(factory);
+        self
+    }
+
+    /// Registers an application with the given options, E.g. a restart policy, a name or the
+    /// applications it depends on.
+    pub fn application_with_options<F>(mut self, options: ApplicationOptions, factory: F) -> ServiceBuilder
+        where F: Fn() -> Box<dyn SimpleApplication + Send + Sync> + Send + Sync + 'static {
+        let result = self.registry.register_with_options(options, Box::new(factory)).map(|_| ());
+        self.record_registration(result)
+    }
+
+    /// Registers an async application, which runs on the Tokio runtime of the host.
+    pub fn async_application<F>(mut self, factory: F) -> ServiceBuilder
+        where F: Fn() -> Arc<dyn AsyncApplication> + Send + Sync + 'static {
+        self.registry.register_async(factory);
+        self
+    }
+
+    pub fn async_application_with_options<F>(mut self, options: ApplicationOptions, factory: F) -> ServiceBuilder
+        where F: Fn() -> Arc<dyn AsyncApplication> + Send + Sync + 'static {
+        let result = self.registry.register_async_with_options(options, Box::new(factory)).map(|_| ());
+        self.record_registration(result)
+    }
+
+    /// Registers an application which gets its section of the configuration, E.g.
+    /// `|config: WorkerConfig| { Box::new(Worker::new(config)) }`. The application must have a
+    /// name, which names the section.
+    pub fn configured_application<C, F>(mut self, options: ApplicationOptions, factory: F) -> ServiceBuilder
+        where C: DeserializeOwned, F: Fn(C) -> Box<dyn SimpleApplication + Send + Sync> + Send + Sync + 'static {
+        let result = self.registry.register_configured(options, factory).map(|_| ());
+        self.record_registration(result)
+    }
+
+    pub fn configured_async_application<C, F>(mut self, options: ApplicationOptions, factory: F) -> ServiceBuilder
+        where C: DeserializeOwned, F: Fn(C) -> Arc<dyn AsyncApplication> + Send + Sync + 'static {
+        let result = self.registry.register_async_configured(options, factory).map(|_| ());
+        self.record_registration(result)
+    }
+
+    /// Registers a scheduled job. The application is named after the job unless the options
+    /// give another name.
+    pub fn scheduled_application(mut self, mut options: ApplicationOptions, application: ScheduledApplication) -> ServiceBuilder {
+        if options.name.is_none() {
+            options.name = Some(String::from(application.name()));
+        }
+        let factory = move || -> Box<dyn SimpleApplication + Send + Sync> { Box::new(application.clone()) };
+        let result = self.registry.register_with_options(options, Box::new(factory)).map(|_| ());
+        self.record_registration(result)
+    }
+
+    pub fn applications(mut self, registry: ApplicationRegistry) -> ServiceBuilder {
+        let result = self.registry.append(registry).map(|_| ());
+        self.record_registration(result)
+    }
+
+    pub fn config(&self) -> &ServiceConfig {
+        &self.config
+    }
+
+    pub fn run(self) -> ServiceResult<()> {
+        if let Some(registration_error) = self.registration_error {
+            return Err(registration_error);
+        }
+        let run_mode = self.config.run_mode;
+        let backend = self.backend.unwrap_or_else(|| { host::backend_for(run_mode) });
+        service_wrapper::run_with_config(backend.as_ref(), self.config, self.registry)
+    }
+
+    // The first registration error wins.
+    fn record_registration(mut self, result: ServiceResult<()>) -> ServiceBuilder {
+        if let Err(e) = result {
+            log::error!("Fail to register the application: {}", e);
+            self.registration_error.get_or_insert(e);
+        }
+        self
+    }
+}
+
+impl Default for ServiceBuilder {
+    fn default() -> Self {
+        ServiceBuilder::new()
+    }
+}
@@ -0,0 +1,177 @@
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::ops::BitOr;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::configuration::{CONFIG_ARGUMENT, CONFIG_ENVIRONMENT_VARIABLE, DEFAULT_ENVIRONMENT_PREFIX};
+use crate::host::{self, RunMode};
+
+/// Passes the service name as a start argument: `--service-name <name>`.
+pub const SERVICE_NAME_ARGUMENT: &str = "--service-name";
+/// Passes the service name through the environment.
+pub const SERVICE_NAME_ENVIRONMENT_VARIABLE: &str = "SERVICE_NAME";
+
+const DEFAULT_SERVICE_NAME: &str = "service";
+const DEFAULT_START_WAIT_HINT: Duration = Duration::from_secs(10);
+const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_STOP_WAIT_HINT: Duration = Duration::from_secs(10);
+const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How the service process is shared with other services. Only the Windows service control
+/// manager cares about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceType {
+    OwnProcess,
+    ShareProcess,
+    UserOwnProcess,
+    UserShareProcess,
+}
+
+/// The control requests the service accepts while it is running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcceptedControls(u32);
+
+impl AcceptedControls {
+    pub const STOP: AcceptedControls = AcceptedControls(0x1);
+    pub const PAUSE_CONTINUE: AcceptedControls = AcceptedControls(0x2);
+    pub const SHUTDOWN: AcceptedControls = AcceptedControls(0x4);
+    pub const PRESHUTDOWN: AcceptedControls = AcceptedControls(0x8);
+    /// Reloads the configuration on the ParamChange control (SIGHUP on unix). Please refer to
+    /// the `configuration` module.
+    pub const PARAM_CHANGE: AcceptedControls = AcceptedControls(0x10);
+
+    pub const fn empty() -> AcceptedControls {
+        AcceptedControls(0)
+    }
+
+    pub const fn contains(&self, other: AcceptedControls) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for AcceptedControls {
+    type Output = AcceptedControls;
+
+    fn bitor(self, other: AcceptedControls) -> AcceptedControls {
+        AcceptedControls(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub service_name: String,
+    pub service_type: ServiceType,
+    pub accepted_controls: AcceptedControls,
+    /// The time the service manager should wait for the service to start.
+    pub start_wait_hint: Duration,
+    /// The time the applications have to initialize. The service fails to start with
+    /// `exit_code::START_FAILURE` if they are not initialized by then.
+    pub start_timeout: Duration,
+    /// The time the service manager should wait for the next progress report while the service
+    /// is stopping.
+    pub stop_wait_hint: Duration,
+    /// The time each application has to exit after the service is requested to stop. The
+    /// applications still running afterwards are abandoned, and the service exits with
+    /// `exit_code::STOP_TIMEOUT`.
+    pub stop_timeout: Duration,
+    /// The time the applications have to exit when the machine is shutting down. The host
+    /// stops waiting for the applications afterwards.
+    pub shutdown_grace_period: Duration,
+    /// The number of worker threads of the Tokio runtime which runs the async applications. The
+    /// runtime is only created if there are async applications. None means one worker thread
+    /// per CPU core.
+    pub async_worker_threads: Option<usize>,
+    /// The localhost port of the health endpoints (`/healthz` and `/readyz`). None means the
+    /// endpoints are disabled.
+    pub health_port: Option<u16>,
+    /// The localhost port of the Prometheus metrics endpoint (`/metrics`). It may be the health
+    /// port. None means the endpoint is disabled.
+    pub metrics_port: Option<u16>,
+    /// How often the health of the applications is checked.
+    pub health_check_interval: Duration,
+    /// The configuration file of the applications. Please refer to the `configuration` module.
+    pub config_file: Option<PathBuf>,
+    /// The prefix of the environment variables which override the configuration file.
+    pub config_environment_prefix: String,
+    /// The signals translated to user-defined control codes on unix, E.g. `(SIGUSR1, 130)`.
+    /// The signals the host handles already (E.g. SIGTERM or SIGHUP) can't be mapped. Please
+    /// refer to the `command` module.
+    pub user_control_signals: Vec<(i32, u8)>,
+    pub run_mode: RunMode,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        ServiceConfig {
+            service_name: default_service_name(),
+            service_type: ServiceType::OwnProcess,
+            accepted_controls: AcceptedControls::STOP | AcceptedControls::SHUTDOWN,
+            start_wait_hint: DEFAULT_START_WAIT_HINT,
+            start_timeout: DEFAULT_START_TIMEOUT,
+            stop_wait_hint: DEFAULT_STOP_WAIT_HINT,
+            stop_timeout: DEFAULT_STOP_TIMEOUT,
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            async_worker_threads: None,
+            health_port: None,
+            metrics_port: None,
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            config_file: default_config_file(),
+            config_environment_prefix: String::from(DEFAULT_ENVIRONMENT_PREFIX),
+            user_control_signals: vec![],
+            run_mode: host::run_mode_from_environment(),
+        }
+    }
+}
+
+// The service name is read from (in order):
+//
+// (1) the `--service-name` start argument.
+// (2) the `SERVICE_NAME` environment variable.
+// (3) the file name of the executable, which is the convention used by our install scripts.
+pub fn default_service_name() -> String {
+    argument_value(SERVICE_NAME_ARGUMENT)
+        .or_else(|| env::var(SERVICE_NAME_ENVIRONMENT_VARIABLE).ok().filter(|name| !name.is_empty()))
+        .or_else(service_name_from_executable)
+        .unwrap_or_else(|| String::from(DEFAULT_SERVICE_NAME))
+}
+
+// The configuration file is read from the `--config` start argument, or the SERVICE_CONFIG
+// environment variable. There is no configuration file by default.
+pub fn default_config_file() -> Option<PathBuf> {
+    argument_value(CONFIG_ARGUMENT)
+        .or_else(|| env::var(CONFIG_ENVIRONMENT_VARIABLE).ok().filter(|path| !path.is_empty()))
+        .map(PathBuf::from)
+}
+
+// Reads `<name> <value>` or `<name>=<value>` from the start arguments. `env::args` panics on an
+// argument which is not valid Unicode, such an argument is skipped instead, with a warning if it
+// is the value looked for.
+fn argument_value(name: &str) -> Option<String> {
+    let mut arguments = env::args_os().skip(1);
+    while let Some(argument) = arguments.next() {
+        if argument == OsStr::new(name) {
+            return arguments.next().and_then(|value| unicode_argument(name, value));
+        }
+        let argument = match argument.to_str() {
+            Some(argument) => argument,
+            None => continue,
+        };
+        if let Some(value) = argument.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(String::from(value));
+        }
+    }
+    None
+}
+
+fn unicode_argument(name: &str, value: OsString) -> Option<String> {
+    value.into_string()
+        .map_err(|value| log::warn!("Start argument {} {} is not valid Unicode, it is ignored.", name, value.to_string_lossy()))
+        .ok()
+}
+
+fn service_name_from_executable() -> Option<String> {
+    env::current_exe().ok()
+        .and_then(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+}
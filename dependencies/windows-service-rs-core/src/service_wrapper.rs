@@ -1,89 +1,83 @@
-use std::{ffi::OsString, time::Duration, thread};
-use windows_service::{
-    define_windows_service,
-    service::{
-        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
-        ServiceType,
-    },
-    service_control_handler::{self, ServiceControlHandlerResult},
-    service_dispatcher
-};
-use crate::error::{ServiceResult, ServiceError};
-use windows_service::service_control_handler::ServiceStatusHandle;
-use std::sync::atomic::{Ordering, AtomicBool};
-use std::sync::Arc;
+use std::{time::{Duration, Instant}, thread};
+use std::sync::atomic::{Ordering, AtomicU32};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::thread::JoinHandle;
-use crate::application::SimpleApplication;
+use tokio::runtime::{self, Handle, Runtime};
+use crate::command::{self, CommandDispatcher};
+use crate::configuration::{Configuration, ConfigurationChange, SharedConfiguration};
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::exit_code;
+use crate::pause_signal::PauseSignal;
+use crate::run_context::{RunContext, StopReason};
+use crate::shutdown_token::ShutdownToken;
+use crate::registry::{ApplicationRegistry, RegisteredApplication};
+use crate::supervisor::{self, ReplicaSet, StartGates, SupervisorEvent};
+use crate::health_monitor::HealthMonitor;
+use crate::watchdog::Watchdog;
+use crate::endpoints::Endpoints;
+use crate::message_bus::MessageBus;
+use crate::metrics::MetricsRegistry;
+use crate::dependency::DependencyGraph;
+use crate::host::{HostBackend, HostControl, HostSession, HostState, HostStatus, StatusReporter};
+use crate::service_builder::ServiceBuilder;
+use crate::service_config::{AcceptedControls, ServiceConfig};
 
-const SERVICE_NAME: &str = "sample_service";
-const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+const CONTROL_POLLING_INTERVAL: Duration = Duration::from_millis(100);
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
-static mut APPLICATION:Vec<fn() -> Box<dyn SimpleApplication>> = Vec::new();
-
-fn get_application() -> &'static Vec<fn() -> Box<dyn SimpleApplication>> {
-    unsafe { &APPLICATION }
+pub fn run(registry:ApplicationRegistry) -> ServiceResult<()> {
+    ServiceBuilder::new().applications(registry).run()
 }
 
-fn initialize_application(factories:Vec<fn() -> Box<dyn SimpleApplication>>) -> ServiceResult<()> {
-    unsafe { APPLICATION = factories; }
-    Ok(())
+pub fn run_with_backend(
+    backend:&dyn HostBackend,
+    registry:ApplicationRegistry
+) -> ServiceResult<()> {
+    run_with_config(backend, ServiceConfig::default(), registry)
 }
 
-pub fn run(factories:Vec<fn() -> Box<dyn SimpleApplication>>) -> ServiceResult<()> {
-    // The service_dispatcher::start() function does the same thing in a typical window
-    // service. That is:
-    // (1) register service entry point to the service table
-    // (2) call the service dispatcher to invoke the main function of the service.
-    //
-    // C++ equivalent
-    // ------------------------------------------------------------------------------
-    // SERVICE_TABLE_ENTRY ServiceTable[] =
-    // {
-    //   {SERVICE_NAME, (LPSERVICE_MAIN_FUNCTION) ffi_service_main},
-    //   {NULL, NULL}
-    // };
-    //
-    // if (StartServiceCtrlDispatcher (ServiceTable) == FALSE)
-    // {
-    //   return GetLastError ();
-    // }
+pub fn run_with_config(
+    backend:&dyn HostBackend,
+    config:ServiceConfig,
+    registry:ApplicationRegistry
+) -> ServiceResult<()> {
+    // The backend connects to the service manager of the platform and invokes the service
+    // main function once the service manager is ready. Please refer to the `host` module for
+    // details.
+    log::info!("Running service {} with {}.", config.service_name, backend.name());
     //
-    // return 0;
-    // ------------------------------------------------------------------------------
-    initialize_application(factories)?;
-    service_dispatcher::start(SERVICE_NAME, ffi_service_main)
-        .map_err(|e| { ServiceError::with(e, "Fail to call service dispatcher. ") })
+    // The registry is moved into the service main function, so nothing but the service main
+    // function itself crosses the backend.
+    let config_for_service = config.clone();
+    backend.run(&config, Box::new(move |session| {
+        run_service(session, config_for_service, registry)
+    }))
 }
 
-// The macro here is used to handle common argument processing logic for us. It defines a
-// function in the first argument (in this case `ffi_service_main`). In that function it
-// parse the starting arguments and then passes the arguments to the the function defined
-// by the second argument.
-//
-// So the expanded form is something like:
-//
-// fn ffi_service_main(num_service_arguments:u32, service_arguments: **u16) {
-//   let arguments = parse_service_arguments(num_service_arguments, service_arguments);
-//   sample_service_main(arguments);
-// }
-define_windows_service!(ffi_service_main, sample_service_main);
-
-fn sample_service_main(_arguments: Vec<OsString>) {
-    // The sample_service_main is called by ffi_service_main. The ffi_service_main follows the
-    // definition LPSERVICE_MAIN_FUNCTION:
-    //
-    // void LpserviceMainFunction(
-    //   DWORD dwNumServicesArgs,
-    //   LPTSTR *lpServiceArgVectors
-    // )
-    //
-    // Thus it will not return any state to the environment. The service just stopped if the
-    // function returns. So if you want to record error message. You would better record in
-    // windows event logs or in the customized log file.
-    run_service().unwrap_or_else(|e| { log::error!("{}", e.message) });
+// Whatever stops the service with an error (E.g. an invalid configuration, the health port is
+// in use, or the service manager rejects a status report), the service manager is told that the
+// service has stopped, with the exit code of the error. Otherwise the service would stay in its
+// last state (E.g. StartPending) until the service manager times out.
+fn run_service(
+    session:HostSession,
+    config:ServiceConfig,
+    registry:ApplicationRegistry
+) -> ServiceResult<()> {
+    let reporter = session.reporter.clone();
+    let stopped = status_of(HostState::Stopped, &config);
+    run_applications(session, config, registry).inspect_err(|e| {
+        reporter.report(stopped.with_exit_code(e.exit_code)).unwrap_or_else(|report_error| {
+            log::error!("Fail to report the stopped service: {}", report_error);
+        });
+    })
 }
 
-fn run_service() -> ServiceResult<()> {
+fn run_applications(
+    session:HostSession,
+    config:ServiceConfig,
+    registry:ApplicationRegistry
+) -> ServiceResult<()> {
     // This method contains the main service handling logic. To run a service, we need to do
     // the following initializations (sequential):
     //
@@ -101,147 +95,639 @@ fn run_service() -> ServiceResult<()> {
     // (9) Change service status to stop.
     // (10) Exit.
 
-    // Now we do (1)
-    //
-    // Since the status callback is an async callback. We have to had a sync mechanism to do the
-    // communication. Just like a message queue. So we create a channel to send the service status
-    // to the callback.
-    let exit_signal = Arc::new(AtomicBool::new(false));
-
-    // To register the callback, we need to declare the callback first. The callback accepts the
-    // desired service status (defined in service::ServiceControl) and returns the
-    // service_control_handler::ServiceControlHandlerResult.
+    // (1) is done by the backend. The control requests are sent to us through
+    //     `session.controls`.
     //
-    // C++ equivalent
-    // ------------------------------------------------------------------------------
-    // VOID WINAPI EventHandler(DWORD ctrlEvent)
-    // {
-    //   switch (ctrlEvent)
-    //   {
-    //   case SERVICE_CONTROL_STOP :
-    //     if (g_ServiceStatus.dwCurrentState != SERVICE_RUNNING)
-    //       break;
-    //
-    //     g_ServiceStatus.dwControlsAccepted = 0;
-    //     g_ServiceStatus.dwCurrentState = SERVICE_STOP_PENDING;
-    //     g_ServiceStatus.dwWin32ExitCode = 0;
-    //     g_ServiceStatus.dwCheckPoint = 4;
-    //
-    //     if (SetServiceStatus (g_StatusHandle, &g_ServiceStatus) == FALSE)
-    //     {
-    //       // Log something here.
-    //     }
-    //
-    //     // Stop the service anyway.
-    //     SetEvent (g_ServiceStopEvent);
-    //     break;
+    //     The dependencies are checked before the applications start, so a missing dependency
+    //     fails at once. The service main function is running already, so that the service
+    //     manager gets the exit code of the error (E.g. rather than a dispatcher which never
+    //     connects on Windows).
+    let dependencies = DependencyGraph::new(registry.applications())?;
+    //     So is the configuration file. The sections of the applications are deserialized when
+    //     the applications are created, please refer to the `configuration` module.
+    let configuration = Configuration::load(config.config_file.as_deref(), &config.config_environment_prefix)?;
+    for application in registry.applications() {
+        replica_count(&configuration, application)?;
+    }
+    let configuration: SharedConfiguration = Arc::new(RwLock::new(Arc::new(configuration)));
+    let mut reporter = session.reporter;
+    let shutdown_token = ShutdownToken::new();
+    let stop_reason = Arc::new(Mutex::new(None));
+    let mut checkpoint: u32 = 0;
+    //     The health and metrics endpoints are served from the start, so that the probes see
+    //     the service starting. Please refer to the `endpoints` module for details.
+    let replica_sets: Vec<ReplicaSet> = registry.applications().iter().map(|_| ReplicaSet::default()).collect();
+    let metrics = MetricsRegistry::new();
+    let health = config.health_port.map(|_| { Arc::new(HealthMonitor::new(registry.applications(), &replica_sets)) });
+    if let Some(health) = &health {
+        reporter = health.track(reporter);
+    }
+    let endpoints = if config.health_port.is_some() || config.metrics_port.is_some() {
+        Some(Endpoints::start(&config, health, metrics.clone())?)
+    } else {
+        None
+    };
+    //     The user-defined commands are handled on a thread of their own, please refer to the
+    //     `command` module.
+    let commands = CommandDispatcher::start(registry.applications(), &replica_sets);
+    //     The applications talk to each other through the message bus, please refer to the
+    //     `message_bus` module.
+    let bus = MessageBus::with_metrics(metrics.clone());
+    let stop_duration = metrics.gauge(
+        "service_stop_duration_seconds", "The time since the service is requested to stop, in seconds.");
+
     //
-    //   case ...
+    // (2) Set service status as start pending.
     //
-    //   default:
-    //     break;
-    //   }
-    // }
-    // ------------------------------------------------------------------------------
-    let exit_signal_for_service_event_handler = exit_signal.clone();
-    let event_handler = move |control_event| -> ServiceControlHandlerResult {
-        match control_event {
-            // Notifies a service to report its current status information to the service
-            // control manager. Always return NoError even if not implemented.
-            ServiceControl::Interrogate => {
-                ServiceControlHandlerResult::NoError
+    reporter.report(status_of(HostState::StartPending, &config))?;
+
+    // (3) Do some initialization work here.
+    //     The async applications share a multi-thread Tokio runtime owned by the host. It is
+    //     only created if there are async applications.
+    let runtime = if registry.has_async_applications() { Some(build_runtime(&config)?) } else { None };
+    //     Each replica of an application runs under a supervisor, which restarts it according
+    //     to its restart policy. The supervisor creates and initializes the application once
+    //     its initialize gate opens, which is when all its dependencies are initialized. But it
+    //     doesn't run the application until the run gate opens. Please refer to the
+    //     `supervisor` module for details.
+    let (supervisor_sender, supervisor_events) = mpsc::channel();
+    let launcher = ReplicaLauncher {
+        stop_reason: stop_reason.clone(),
+        metrics: metrics.clone(),
+        configuration: configuration.clone(),
+        bus: bus.clone(),
+        events: supervisor_sender,
+        runtime: runtime.as_ref().map(|runtime| runtime.handle().clone()),
+        run_gate: ShutdownToken::new(),
+    };
+    //     The watchdog detects the applications which miss their heartbeat, and keeps the
+    //     service manager informed that the service is alive. Please refer to the `watchdog`
+    //     module.
+    let watchdog = Watchdog::start(registry.applications(), &replica_sets, reporter.clone(), launcher.events.clone());
+    let initial_configuration = current_configuration(&configuration);
+    let mut applications:Vec<RunningApplication> = vec![];
+    for (index, application) in registry.applications().iter().enumerate() {
+        let mut app = RunningApplication {
+            identity: application.identity(index),
+            application: application.clone(),
+            token: shutdown_token.child(),
+            initialize_gate: ShutdownToken::new(),
+            stop_stage: dependencies.stop_stage(index),
+            publishes: true,
+            replica_set: replica_sets[index].clone(),
+            replicas: vec![],
+        };
+        let replicas = replica_count(&initial_configuration, application).unwrap_or(application.options().replicas);
+        app.scale(index, replicas, &launcher, false);
+        applications.push(app);
+    }
+    let start_failure = wait_for_initialization(
+        reporter.as_ref(), &config, &supervisor_events, &applications, &dependencies, &mut checkpoint)?;
+
+    // (4) Set service status as running. If the service fails to start, it never runs: the
+    //     applications see the exit request as soon as the gates open, and the service goes
+    //     straight to stopping.
+    let mut state = HostState::StartPending;
+    let mut failure: Option<ServiceError> = None;
+    let mut pending_stop: Option<(StopReason, Duration)> = None;
+    match start_failure {
+        None => {
+            state = HostState::Running;
+            reporter.report(status_of(state, &config))?;
+        },
+        Some(start_failure) => {
+            log::error!("The service fails to start: {}", start_failure);
+            request_stop(&stop_reason, StopReason::ApplicationFailure);
+            shutdown_token.cancel();
+            applications.iter().for_each(|app| app.initialize_gate.cancel());
+            failure = Some(start_failure);
+            pending_stop = Some((StopReason::ApplicationFailure, config.stop_timeout));
+        },
+    }
+
+    // (5) Create a threat for the main service loop. Waiting for event to gracefully change service
+    //     status.
+    //     The supervisors are created in (3), opening the run gate lets them run the
+    //     applications.
+    launcher.run_gate.cancel();
+
+    // (6) Waiting for the main service loop to exit. Meanwhile, the control requests from the
+    //     service manager are dispatched to the applications.
+    let accepts_pause = config.accepted_controls.contains(AcceptedControls::PAUSE_CONTINUE);
+    let accepts_param_change = config.accepted_controls.contains(AcceptedControls::PARAM_CHANGE);
+    // Once a stop is requested, the applications have to exit before the stop deadline. In the
+    // meantime, the stop progress is reported periodically with an increasing checkpoint, so
+    // that the service manager knows the service is not hung. The applications still running
+    // after the deadline are abandoned: they are killed with the process.
+    let mut stop_deadline: Option<Instant> = None;
+    let mut stop_requested_at: Option<Instant> = None;
+    let mut last_progress = Instant::now();
+    // The applications are requested to exit stage by stage, the applications of a stage once
+    // the applications of the previous stages have exited, so that an application stops before
+    // the applications it depends on. Please refer to the `dependency` module for details.
+    let mut stop_stage: usize = 0;
+    while !applications.iter().all(|app| app.is_finished()) {
+        // An application which has exited for good doesn't publish any more, which closes the
+        // topics it publishes on if they are draining.
+        for (index, app) in applications.iter_mut().enumerate() {
+            if app.publishes && app.is_finished() {
+                app.publishes = false;
+                bus.close_application(index);
+            }
+        }
+
+        let mut stop_request: Option<(StopReason, Duration)> = pending_stop.take();
+        match session.controls.recv_timeout(CONTROL_POLLING_INTERVAL) {
+            Ok(HostControl::Stop) => stop_request = Some((StopReason::Operator, config.stop_timeout)),
+
+            // The machine won't wait for us when it is shutting down, so the applications only
+            // have the shutdown grace period.
+            Ok(HostControl::Shutdown) | Ok(HostControl::Preshutdown) =>
+                stop_request = Some((StopReason::Shutdown, config.shutdown_grace_period)),
+
+            Ok(HostControl::Pause) if accepts_pause && state == HostState::Running => {
+                state = HostState::PausePending;
+                reporter.report(status_of(state, &config))?;
+                applications.iter().for_each(|app| app.request_pause());
+            },
+
+            Ok(HostControl::Continue) if accepts_pause
+                && (state == HostState::Paused || state == HostState::PausePending) => {
+                state = HostState::ContinuePending;
+                reporter.report(status_of(state, &config))?;
+                applications.iter().for_each(|app| app.request_continue());
             },
 
-            // Handle stop
-            ServiceControl::Stop => {
-                exit_signal_for_service_event_handler.store(true, Ordering::SeqCst);
-                ServiceControlHandlerResult::NoError
+            // The applications may be paused or stopping otherwise, and they couldn't take a
+            // restart.
+            Ok(HostControl::ParamChange) if accepts_param_change && state == HostState::Running => {
+                for (index, replicas) in reload_configuration(&config, &registry, &configuration, &replica_sets) {
+                    applications[index].scale(index, replicas, &launcher, false);
+                }
             },
 
-            _ => ServiceControlHandlerResult::NotImplemented,
+            Ok(HostControl::User(code)) if state != HostState::StopPending => {
+                let paused = state == HostState::Paused || state == HostState::PausePending;
+                match command::find_scale_command(registry.applications(), code) {
+                    Some((index, up)) => applications[index].scale_by_one(index, up, &launcher, paused),
+                    None if !commands.dispatch(code) => log::warn!("Control code {} is not registered by any application.", code),
+                    None => {},
+                }
+            },
+
+            Ok(control) => {
+                log::warn!("Control {:?} is ignored in state {:?}.", control, state);
+            },
+
+            // The backend will not send any control request any more (E.g. the SCM has
+            // released the control handler). Just wait for the applications.
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(CONTROL_POLLING_INTERVAL),
+            Err(RecvTimeoutError::Timeout) => {},
+        }
+
+        match supervisor_events.try_recv() {
+            Ok(SupervisorEvent::Failed(error)) => {
+                failure.get_or_insert(error);
+                stop_request = Some((StopReason::ApplicationFailure, config.stop_timeout));
+            },
+            // A replica started by scaling up the application.
+            Ok(SupervisorEvent::Initialized(index, replica, Ok(()))) =>
+                log::info!("{} is initialized.", applications[index].application.replica_identity(index, replica)),
+            Ok(SupervisorEvent::Initialized(index, replica, Err(e))) =>
+                log::error!("{} fails to initialize: {}", applications[index].application.replica_identity(index, replica), e),
+            Err(_) => {},
+        }
+
+        if let Some((reason, timeout)) = stop_request {
+            log::info!("Stop requested ({:?}), the applications have {:?} to exit.", reason, timeout);
+            request_stop(&stop_reason, reason);
+            stop_requested_at.get_or_insert_with(Instant::now);
+            let deadline = Instant::now() + timeout;
+            stop_deadline = Some(stop_deadline.map_or(deadline, |current| current.min(deadline)));
+            if state != HostState::StopPending {
+                state = HostState::StopPending;
+                checkpoint += 1;
+                last_progress = Instant::now();
+                reporter.report(status_of(state, &config).with_checkpoint(checkpoint))?;
+            }
+        }
+
+        if let Some(deadline) = stop_deadline {
+            stop_duration.set(stop_requested_at.map(|at| { at.elapsed().as_secs_f64() }).unwrap_or_default());
+            while applications.iter().any(|app| app.stop_stage >= stop_stage)
+                && applications.iter().filter(|app| app.stop_stage < stop_stage).all(|app| app.is_finished()) {
+                log::info!("Stopping the applications of stage {}.", stop_stage);
+                applications.iter().enumerate().filter(|(_, app)| app.stop_stage == stop_stage).for_each(|(index, app)| {
+                    // The topics the application subscribes to close once their publishers
+                    // (which stop before it) have exited, so that it gets the queued messages.
+                    bus.drain(index, |publisher| { dependencies.stop_stage(publisher) > stop_stage });
+                    app.token.cancel();
+                    // Paused applications have to wake up to see the exit signal.
+                    app.request_continue();
+                });
+                stop_stage += 1;
+            }
+
+            if Instant::now() >= deadline {
+                log::warn!("The stop deadline is over, stop waiting for the applications.");
+                // The applications of the remaining stages are not requested to exit yet.
+                shutdown_token.cancel();
+                break;
+            }
+            if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                checkpoint += 1;
+                last_progress = Instant::now();
+                reporter.report(status_of(state, &config).with_checkpoint(checkpoint))?;
+            }
+        }
+
+        // The service is paused only when all the applications which support pausing have
+        // acknowledged the request (or exited), and it is running again when all of them
+        // have resumed.
+        if state == HostState::PausePending && applications.iter().all(|app| app.is_paused_or_finished()) {
+            state = HostState::Paused;
+            reporter.report(status_of(state, &config))?;
+        } else if state == HostState::ContinuePending && !applications.iter().any(|app| app.is_paused()) {
+            state = HostState::Running;
+            reporter.report(status_of(state, &config))?;
+        }
+    }
+
+    // The last application may fail right before it exits.
+    while let Ok(event) = supervisor_events.try_recv() {
+        if let SupervisorEvent::Failed(error) = event {
+            failure.get_or_insert(error);
+        }
+    }
+
+    let mut abandoned_applications: Vec<usize> = vec![];
+    for (index, app) in applications.into_iter().enumerate() {
+        if !app.is_finished() {
+            abandoned_applications.push(index);
         }
+        app.replicas.into_iter().for_each(RunningReplica::join);
+    }
+
+    // (8) Do some recycle work.
+    if let Some(stop_requested_at) = stop_requested_at {
+        log::info!("The applications are stopped in {:?}.", stop_requested_at.elapsed());
+        stop_duration.set(stop_requested_at.elapsed().as_secs_f64());
+    }
+    if let Some(watchdog) = watchdog {
+        watchdog.stop();
+    }
+    if let Some(endpoints) = endpoints {
+        endpoints.stop();
+    }
+    //     A command handler and the tasks spawned by the async applications may still be
+    //     running. They have what is left of the stop deadline to finish, and are abandoned
+    //     after that.
+    let remaining = || {
+        stop_deadline
+            .map(|deadline| { deadline.saturating_duration_since(Instant::now()) })
+            .unwrap_or(config.stop_timeout)
     };
+    commands.stop(remaining());
+    bus.close();
+    if let Some(runtime) = runtime {
+        runtime.shutdown_timeout(remaining());
+    }
 
-    // Register system service event handler. The returned status handle should be used to
-    // report service status changes to the system.
-    //
-    // C++ equivalent
-    // ------------------------------------------------------------------------------
-    // g_StatusHandle = RegisterServiceCtrlHandler (SERVICE_NAME, EventHandler);
-    // ------------------------------------------------------------------------------
-    let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)
-        .map_err(|e| { ServiceError::with(e, "Fail to register windows service. ") })?;
+    // (7) Change service status to stop pending. It is already reported if the service is
+    //     requested to stop.
+    if state != HostState::StopPending {
+        checkpoint += 1;
+        reporter.report(status_of(HostState::StopPending, &config).with_checkpoint(checkpoint))?;
+    }
 
-    //
-    // (2) Set service status as start pending.
-    //
-    // Each time we update the service status we need to tell the service controller what
-    // current status is, what kind of controls we can do next, what is the checkpoint value
-    set_service_status_with_empty_control(&status_handle, ServiceState::StartPending)?;
+    // (9) Change service status to stop. The exit code tells why the service stops if it is not
+    //     requested to, `run_service` reports it.
+    if failure.is_none() && !abandoned_applications.is_empty() {
+        let message = format!("Applications {:?} do not exit before the stop deadline. ", abandoned_applications);
+        failure = Some(ServiceError::of_kind(ErrorKind::Timeout, message).with_exit_code(exit_code::STOP_TIMEOUT));
+    }
+    if let Some(failure) = failure {
+        return Err(failure);
+    }
+    reporter.report(status_of(HostState::Stopped, &config))?;
 
-    // (3) Do some initialization work here.
-    let applications = get_application();
+    // (10) Exit.
+    log::info!("All done. Exit service.");
+    Ok(())
+}
 
-    // (4) Set service status as running.
-    set_service_status(&status_handle, ServiceState::Running, ServiceControlAccept::STOP)?;
+// Waits for the applications to be initialized, and reports the start progress periodically with
+// an increasing checkpoint meanwhile. An application is initialized when all its replicas are.
+// The initialize gate of an application opens when all its dependencies are initialized.
+//
+// Returns the failure if an application fails to initialize or if the start timeout is over.
+fn wait_for_initialization(
+    reporter: &dyn StatusReporter,
+    config: &ServiceConfig,
+    supervisor_events: &Receiver<SupervisorEvent>,
+    applications: &[RunningApplication],
+    dependencies: &DependencyGraph,
+    checkpoint: &mut u32,
+) -> ServiceResult<Option<ServiceError>> {
+    let deadline = Instant::now() + config.start_timeout;
+    let mut last_progress = Instant::now();
+    let mut pending: Vec<usize> = applications.iter().map(|app| app.replicas.len()).collect();
+    while pending.iter().any(|pending| *pending > 0) {
+        for (index, app) in applications.iter().enumerate() {
+            if !app.initialize_gate.is_cancelled()
+                && dependencies.dependencies(index).iter().all(|dependency| pending[*dependency] == 0) {
+                app.initialize_gate.cancel();
+            }
+        }
 
-    // (5) Create a threat for the main service loop. Waiting for event to gracefully change service
-    //     status.
-    let mut thread_handles:Vec<JoinHandle<()>> = vec![];
-    for factory in applications {
-        let exit_signal_for_app = exit_signal.clone();
-        let handle = thread::spawn(move || {
-            let app: Box<dyn SimpleApplication> = factory();
-            app.run(exit_signal_for_app).unwrap_or_else(|e| {
-                app.handle_error(&e);
-            });
+        let now = Instant::now();
+        if now >= deadline {
+            let message = format!("The applications are not initialized within {:?}. ", config.start_timeout);
+            return Ok(Some(ServiceError::of_kind(ErrorKind::Timeout, message).with_exit_code(exit_code::START_FAILURE)));
+        }
+        match supervisor_events.recv_timeout(CONTROL_POLLING_INTERVAL.min(deadline - now)) {
+            Ok(SupervisorEvent::Initialized(index, replica, Ok(()))) => {
+                log::info!("{} is initialized.", applications[index].application.replica_identity(index, replica));
+                pending[index] -= 1;
+            },
+            // The error of the application tells the exit code of the service, like the failure
+            // of a critical application does.
+            Ok(SupervisorEvent::Initialized(index, replica, Err(e))) => {
+                let exit_code = if e.exit_code != exit_code::FAILURE { e.exit_code } else { exit_code::START_FAILURE };
+                let message = format!("{} fails to initialize. ", applications[index].application.replica_identity(index, replica));
+                return Ok(Some(ServiceError::caused_by(e, &message).with_exit_code(exit_code)));
+            },
+            // The applications don't run until the service is started.
+            Ok(event) => log::warn!("Event {:?} is ignored while starting.", event),
+            Err(_) => {},
+        }
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            *checkpoint += 1;
+            last_progress = Instant::now();
+            reporter.report(status_of(HostState::StartPending, config).with_checkpoint(*checkpoint))?;
+        }
+    }
+    Ok(None)
+}
+
+// Reloads the configuration on the ParamChange control. The reloaded configuration takes effect
+// only if it is valid for all the configured applications (whether they run or not), so that
+// a failed reload leaves the current configuration in effect as a whole. Then the running
+// applications whose section has changed get the change, please refer to `LiveInstance`. The
+// other applications get the new configuration when they are (re)started.
+//
+// Returns the applications whose number of replicas has changed, with the new number.
+fn reload_configuration(
+    config: &ServiceConfig,
+    registry: &ApplicationRegistry,
+    configuration: &SharedConfiguration,
+    replica_sets: &[ReplicaSet],
+) -> Vec<(usize, usize)> {
+    log::info!("Reloading the configuration.");
+    let applications = registry.applications();
+    let reloaded = Configuration::load(config.config_file.as_deref(), &config.config_environment_prefix)
+        .and_then(|reloaded| {
+            for (index, application) in applications.iter().enumerate() {
+                application.check_configuration(&reloaded).and_then(|_| { replica_count(&reloaded, application) }).map_err(|e| {
+                    ServiceError::caused_by(e, &format!("{} rejects the configuration. ", application.identity(index)))
+                        .with_kind(ErrorKind::Config)
+                })?;
+            }
+            Ok(Arc::new(reloaded))
         });
-        thread_handles.push(handle);
+    let reloaded = match reloaded {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            log::error!("The configuration is not reloaded, the current one stays in effect: {}", e);
+            return vec![];
+        },
+    };
+
+    let current = match configuration.write() {
+        Ok(mut current) => std::mem::replace(&mut *current, reloaded.clone()),
+        Err(e) => {
+            log::error!("The configuration is not reloaded: {}", e);
+            return vec![];
+        },
+    };
+    let mut changes = 0;
+    for (index, application) in applications.iter().enumerate() {
+        let name = match application.name() {
+            Some(name) if current.section_changed(&reloaded, name) => name,
+            _ => continue,
+        };
+        changes += 1;
+        let live = replica_sets[index].live();
+        if live.is_empty() {
+            log::info!("{} is not running, it gets the new configuration when it starts.", application.identity(index));
+        }
+        for (replica, live) in live {
+            live.apply_configuration(&application.replica_identity(index, replica), &ConfigurationChange::new(&reloaded, name));
+        }
     }
+    log::info!("The configuration is reloaded, {} application(s) changed.", changes);
+    applications.iter().enumerate()
+        .filter_map(|(index, application)| {
+            let replicas = replica_count(&reloaded, application).unwrap_or(application.options().replicas);
+            let changed = replica_count(&current, application).unwrap_or(application.options().replicas) != replicas;
+            if changed { Some((index, replicas)) } else { None }
+        })
+        .collect()
+}
 
-    for handle in thread_handles {
-        handle.join().unwrap_or_else(|e|{
-            log::error!("Application error: {:?}", e);
+// The number of replicas of the application: the `replicas` section of the configuration
+// overrides the registration.
+fn replica_count(configuration: &Configuration, application: &RegisteredApplication) -> ServiceResult<usize> {
+    let configured = match application.name() {
+        Some(name) => configuration.replicas(name)?,
+        None => None,
+    };
+    Ok(configured.unwrap_or(application.options().replicas))
+}
+
+fn current_configuration(configuration: &SharedConfiguration) -> Arc<Configuration> {
+    configuration.read().map(|current| current.clone()).unwrap_or_else(|e| { e.into_inner().clone() })
+}
+
+fn build_runtime(config: &ServiceConfig) -> ServiceResult<Runtime> {
+    let mut builder = runtime::Builder::new_multi_thread();
+    if let Some(worker_threads) = config.async_worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    builder.enable_all()
+        .thread_name(format!("{}-async", config.service_name))
+        .build()
+        .map_err(|e| { ServiceError::caused_by(e, "Fail to create the async runtime. ") })
+}
+
+fn status_of(state: HostState, config: &ServiceConfig) -> HostStatus {
+    let wait_hint = match state {
+        HostState::StartPending | HostState::ContinuePending => config.start_wait_hint,
+        HostState::StopPending | HostState::PausePending => config.stop_wait_hint,
+        _ => Duration::default(),
+    };
+    HostStatus::new(state).with_wait_hint(wait_hint)
+}
+
+// The first stop request wins: a shutdown while the service is already stopping doesn't change
+// the reason seen by the applications.
+fn request_stop(stop_reason: &Mutex<Option<StopReason>>, reason: StopReason) {
+    stop_reason.lock().map(|mut stop_reason| { stop_reason.get_or_insert(reason); }).unwrap_or_default();
+}
+
+// Everything a replica needs to run. The host launches the replicas when the service starts, and
+// when an application is scaled up.
+struct ReplicaLauncher {
+    stop_reason: Arc<Mutex<Option<StopReason>>>,
+    metrics: MetricsRegistry,
+    configuration: SharedConfiguration,
+    bus: MessageBus,
+    events: Sender<SupervisorEvent>,
+    runtime: Option<Handle>,
+    run_gate: ShutdownToken,
+}
+
+impl ReplicaLauncher {
+    fn launch(&self, index: usize, app: &RunningApplication, replica: usize) -> RunningReplica {
+        let token = app.token.child();
+        let label = app.application.name().map(String::from).unwrap_or_else(|| { index.to_string() });
+        let context = RunContext::from_shutdown_token(token.clone(), PauseSignal::new())
+            .with_stop_reason(self.stop_reason.clone())
+            .with_metrics(self.metrics.with_label("application", &label).with_label("replica", &replica.to_string()))
+            .with_configuration(self.configuration.clone())
+            .with_message_bus(self.bus.for_application(index))
+            .with_replica(replica);
+        let pause_signal = context.pause_signal().clone();
+        let failures = context.failure_counter();
+        let application = app.application.clone();
+        let events = self.events.clone();
+        let runtime = self.runtime.clone();
+        let gates = StartGates { initialize: app.initialize_gate.clone(), run: self.run_gate.clone() };
+        let replica_set = app.replica_set.clone();
+        replica_set.expect(replica, true);
+        let handle = thread::spawn(move || {
+            supervisor::supervise(index, application, context, events, runtime, gates, replica_set);
         });
+        RunningReplica {
+            replica, identity: app.application.replica_identity(index, replica), handle, token, pause_signal, failures, retired: false,
+        }
     }
+}
 
-    // (7) Change service status to stop pending.
-    set_service_status_with_empty_control(&status_handle, ServiceState::StopPending)?;
+struct RunningApplication {
+    identity: String,
+    application: RegisteredApplication,
+    // The parent of the tokens of the replicas.
+    token: ShutdownToken,
+    initialize_gate: ShutdownToken,
+    stop_stage: usize,
+    // Whether the application may still publish on the message bus.
+    publishes: bool,
+    replica_set: ReplicaSet,
+    replicas: Vec<RunningReplica>,
+}
 
-    // (9) Change service status to stop.
-    set_service_status_with_empty_control(&status_handle, ServiceState::Stopped)?;
+impl RunningApplication {
+    fn is_finished(&self) -> bool {
+        self.replicas.iter().all(|replica| replica.handle.is_finished())
+    }
 
-    // (10) Exit.
-    log::info!("All done. Exit windows service.");
-    Ok(())
+    fn is_paused(&self) -> bool {
+        self.replicas.iter().any(|replica| replica.pause_signal.is_paused())
+    }
+
+    fn is_paused_or_finished(&self) -> bool {
+        self.replicas.iter().all(|replica| replica.is_paused_or_finished())
+    }
+
+    fn request_pause(&self) {
+        self.replicas.iter().for_each(|replica| replica.pause_signal.request_pause());
+    }
+
+    fn request_continue(&self) {
+        self.replicas.iter().for_each(|replica| replica.pause_signal.request_continue());
+    }
+
+    // The number of replicas, including the failed ones but not the ones being scaled down.
+    fn count(&self) -> usize {
+        self.replicas.iter().filter(|replica| !replica.retired).count()
+    }
+
+    fn scale_by_one(&mut self, index: usize, up: bool, launcher: &ReplicaLauncher, paused: bool) {
+        match (self.count(), up) {
+            (count, true) => self.scale(index, count + 1, launcher, paused),
+            (1, false) => log::warn!("{} has a single replica, it is not scaled down.", self.identity),
+            (count, false) => self.scale(index, count - 1, launcher, paused),
+        }
+    }
+
+    // Launches the missing replicas, or requests the last replicas to exit. A new replica takes
+    // the lowest index which is not in use: the index of a replica being scaled down is reused
+    // once it has exited.
+    fn scale(&mut self, index: usize, replicas: usize, launcher: &ReplicaLauncher, paused: bool) {
+        // The application has exited for good, E.g. it has failed more than its restart policy
+        // allows.
+        if !self.publishes {
+            log::warn!("{} has exited, it is not scaled.", self.identity);
+            return;
+        }
+        let (exited, running): (Vec<_>, Vec<_>) = self.replicas.drain(..)
+            .partition(|replica| replica.retired && replica.handle.is_finished());
+        self.replicas = running;
+        exited.into_iter().for_each(RunningReplica::join);
+
+        let count = self.count();
+        if count != replicas && !self.replicas.is_empty() {
+            log::info!("Scale {} from {} to {} replica(s).", self.identity, count, replicas);
+        }
+        for _ in count..replicas {
+            let replica = (0..).find(|replica| !self.replicas.iter().any(|running| running.replica == *replica)).unwrap_or_default();
+            let running = launcher.launch(index, self, replica);
+            // A new replica of a paused service starts paused, if it supports pausing.
+            if paused {
+                running.pause_signal.request_pause();
+            }
+            self.replicas.push(running);
+        }
+        let mut retiring: Vec<&mut RunningReplica> = self.replicas.iter_mut().filter(|replica| !replica.retired).collect();
+        retiring.sort_by_key(|replica| replica.replica);
+        for replica in retiring.into_iter().skip(replicas) {
+            log::info!("{} is scaled down, request it to exit.", replica.identity);
+            replica.retired = true;
+            self.replica_set.expect(replica.replica, false);
+            replica.token.cancel();
+            replica.pause_signal.request_continue();
+        }
+    }
 }
 
-fn set_service_status_with_empty_control(
-    status_handle:&ServiceStatusHandle,
-    desired_status:ServiceState
-) -> ServiceResult<()> {
-    set_service_status(
-        status_handle,
-        desired_status,
-        ServiceControlAccept::empty())
+struct RunningReplica {
+    replica: usize,
+    identity: String,
+    handle: JoinHandle<()>,
+    token: ShutdownToken,
+    pause_signal: PauseSignal,
+    failures: Arc<AtomicU32>,
+    // The replica is scaled down.
+    retired: bool,
 }
 
-fn set_service_status(
-    status_handle:&ServiceStatusHandle,
-    desired_status:ServiceState,
-    valid_controls:ServiceControlAccept
-) -> ServiceResult<()> {
-    log::info!("Setting service status for {}: {:?}.", SERVICE_NAME, desired_status);
-    status_handle.set_service_status(ServiceStatus {
-        service_type: SERVICE_TYPE,
-        current_state: desired_status,
-        controls_accepted: valid_controls,
-        exit_code: ServiceExitCode::Win32(0),
-        checkpoint: 0,
-        wait_hint: Duration::default(),
-        process_id: None,
-    }).map_err(|e| {
-        let error_message = format!("Fail to set service status to {:?}. ", desired_status);
-        ServiceError::with(e, &error_message)
-    })
-}
\ No newline at end of file
+impl RunningReplica {
+    fn is_paused_or_finished(&self) -> bool {
+        !self.pause_signal.is_supported() || self.pause_signal.is_paused() || self.handle.is_finished()
+    }
+
+    fn join(self) {
+        let failures = self.failures.load(Ordering::SeqCst);
+        if failures > 0 {
+            log::warn!("{} has failed {} time(s).", self.identity, failures);
+        }
+        if !self.handle.is_finished() {
+            log::error!("{} doesn't exit before the stop deadline and is abandoned.", self.identity);
+            return;
+        }
+        self.handle.join().unwrap_or_else(|e|{
+            log::error!("Application error: {:?}", e);
+        });
+    }
+}
@@ -0,0 +1,199 @@
+use std::pin::pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+// A token created from an external exit signal can be cancelled by setting the flag directly,
+// which doesn't wake the waiting threads. They check the flag at least this often.
+const EXTERNAL_SIGNAL_POLLING_INTERVAL: Duration = Duration::from_millis(100);
+
+type CancelCallback = Box<dyn FnOnce() + Send>;
+
+// The shutdown token tells an application when to stop. Unlike the bare exit signal, a thread
+// waiting on the token is woken up at once when the token is cancelled, so the application
+// doesn't have to poll. For example:
+//
+// while !token.is_cancelled() {
+//     do_some_work();
+//     token.sleep_or_cancel(Duration::from_secs(2));
+// }
+//
+// Cloning the token is cheap, all the clones share the same state. A child token is cancelled
+// with its parent, but cancelling the child doesn't affect the parent, which is handy to stop a
+// sub-task on its own.
+#[derive(Clone)]
+pub struct ShutdownToken {
+    shared: Arc<SharedState>,
+}
+
+struct SharedState {
+    cancelled: Arc<AtomicBool>,
+    external: bool,
+    state: Mutex<TokenState>,
+    changed: Condvar,
+    // Wakes up the async waiters.
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct TokenState {
+    callbacks: Vec<CancelCallback>,
+    children: Vec<Weak<SharedState>>,
+}
+
+impl ShutdownToken {
+    pub fn new() -> ShutdownToken {
+        ShutdownToken::with_flag(Arc::new(AtomicBool::new(false)), false)
+    }
+
+    /// Creates a token sharing the given exit signal, for code which still sets or reads the
+    /// `Arc<AtomicBool>` directly. Prefer `cancel`: setting the flag directly doesn't run the
+    /// callbacks nor cancel the child tokens, and the waiting threads only notice it on their
+    /// next check.
+    pub fn from_exit_signal(exit_signal: Arc<AtomicBool>) -> ShutdownToken {
+        ShutdownToken::with_flag(exit_signal, true)
+    }
+
+    fn with_flag(cancelled: Arc<AtomicBool>, external: bool) -> ShutdownToken {
+        ShutdownToken {
+            shared: Arc::new(SharedState {
+                cancelled,
+                external,
+                state: Mutex::new(TokenState::default()),
+                changed: Condvar::new(),
+                notify: Notify::new(),
+            }),
+        }
+    }
+
+    /// Returns the exit signal of the token, which is set when the token is cancelled. It is
+    /// the adapter for the applications taking an `Arc<AtomicBool>`.
+    pub fn exit_signal(&self) -> Arc<AtomicBool> {
+        self.shared.cancelled.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Cancels the token and its child tokens, wakes up the waiting threads and runs the
+    /// callbacks (on the calling thread). Cancelling a cancelled token does nothing.
+    pub fn cancel(&self) {
+        self.shared.cancel();
+    }
+
+    /// Blocks until the token is cancelled.
+    pub fn wait(&self) {
+        let mut state = self.shared.lock();
+        while !self.is_cancelled() {
+            state = self.shared.wait_slice(state, None);
+        }
+    }
+
+    /// Blocks until the token is cancelled or the timeout elapses. Returns true if the token
+    /// is cancelled.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self.shared.wait_slice(state, Some(deadline - now));
+        }
+        true
+    }
+
+    /// Completes when the token is cancelled. It is the async counterpart of `wait`.
+    pub async fn cancelled(&self) {
+        loop {
+            // Register the waiter before checking the flag, so the notification can't be lost.
+            let mut notified = pin!(self.shared.notify.notified());
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            if self.shared.external {
+                tokio::time::timeout(EXTERNAL_SIGNAL_POLLING_INTERVAL, notified).await.unwrap_or_default();
+            } else {
+                notified.await;
+            }
+        }
+    }
+
+    /// Sleeps for the duration unless the token is cancelled in the meantime. Returns true if
+    /// the whole duration has elapsed, so it fits a loop: `while token.sleep_or_cancel(d) {}`.
+    pub fn sleep_or_cancel(&self, duration: Duration) -> bool {
+        !self.wait_timeout(duration)
+    }
+
+    /// Registers a callback which is called once when the token is cancelled. The callback is
+    /// called at once if the token is already cancelled.
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, callback: F) {
+        let mut state = self.shared.lock();
+        if !self.is_cancelled() {
+            state.callbacks.push(Box::new(callback));
+            return;
+        }
+        drop(state);
+        callback();
+    }
+
+    /// Creates a child token, which is cancelled when this token is cancelled.
+    pub fn child(&self) -> ShutdownToken {
+        let child = ShutdownToken::new();
+        let mut state = self.shared.lock();
+        if self.is_cancelled() {
+            drop(state);
+            child.cancel();
+            return child;
+        }
+        state.children.retain(|child| child.strong_count() > 0);
+        state.children.push(Arc::downgrade(&child.shared));
+        child
+    }
+}
+
+impl Default for ShutdownToken {
+    fn default() -> Self {
+        ShutdownToken::new()
+    }
+}
+
+impl SharedState {
+    fn cancel(&self) {
+        // The callbacks and the children are taken, so cancelling twice does nothing. The flag
+        // may be set already if it is an external exit signal.
+        let mut state = self.lock();
+        self.cancelled.store(true, Ordering::SeqCst);
+        let callbacks = std::mem::take(&mut state.callbacks);
+        let children = std::mem::take(&mut state.children);
+        self.changed.notify_all();
+        self.notify.notify_waiters();
+        // The callbacks may use the token, so they are called without holding the lock.
+        drop(state);
+
+        children.iter().filter_map(|child| child.upgrade()).for_each(|child| child.cancel());
+        callbacks.into_iter().for_each(|callback| callback());
+    }
+
+    fn wait_slice<'a>(&self, state: MutexGuard<'a, TokenState>, timeout: Option<Duration>) -> MutexGuard<'a, TokenState> {
+        let timeout = match (timeout, self.external) {
+            (Some(timeout), true) => Some(timeout.min(EXTERNAL_SIGNAL_POLLING_INTERVAL)),
+            (None, true) => Some(EXTERNAL_SIGNAL_POLLING_INTERVAL),
+            (timeout, false) => timeout,
+        };
+        match timeout {
+            Some(timeout) => self.changed.wait_timeout(state, timeout)
+                .map(|(state, _)| state)
+                .unwrap_or_else(|e| e.into_inner().0),
+            None => self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TokenState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
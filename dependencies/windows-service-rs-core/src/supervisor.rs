@@ -0,0 +1,453 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::Sender;
+use tokio::runtime::Handle;
+use crate::application::SimpleApplication;
+use crate::async_application::{AsyncApplication, BoxFuture};
+use crate::command::UserCommand;
+use crate::configuration::ConfigurationChange;
+use crate::error::{ErrorKind, ServiceError, ServiceResult};
+use crate::exit_code;
+use crate::health::HealthReport;
+use crate::metrics::{Counter, Gauge};
+use crate::panic_guard;
+use crate::registry::{RegisteredApplication, RegisteredFactory};
+use crate::restart_policy::{Escalation, RestartDecision, RestartTracker};
+use crate::run_context::RunContext;
+use crate::shutdown_token::ShutdownToken;
+
+// The events sent by the supervisors to the main service loop.
+#[derive(Debug)]
+pub(crate) enum SupervisorEvent {
+    // The first instance of a replica of the application is created and initialized (or fails
+    // to), by application and replica index.
+    Initialized(usize, usize, ServiceResult<()>),
+    // The failure of the application stops the service: it is a critical application, or it is
+    // restarted too often and its escalation is to stop the service.
+    Failed(ServiceError),
+}
+
+// The supervisor runs on the thread of an application. It runs the application, and when `run`
+// returns (or panics), it decides with the restart policy of the application whether to
+// restart it. Each restart gets a fresh instance from the factory, so no state is carried over
+// from the failed instance.
+//
+// The first instance is created and initialized while the service is starting, once the
+// initialize gate opens (E.g. when the dependencies of the application are initialized). The
+// supervisor reports the result to the main service loop, and waits for the run gate to open
+// before running the application, so that no application runs before the service reports
+// Running. If the service fails to start, the shutdown token is cancelled before the gates
+// open. Failing to initialize a restarted instance is a failure like any other.
+//
+// Each replica of an application runs under a supervisor of its own, with its own restart policy
+// tracker and metrics. The running instance is published in the replica set, so that the health
+// monitor can check it from another thread, and so that the host can deliver a configuration
+// change or a command to it.
+//
+// Each instance runs with a shutdown token of its own, so that the host can restart it (E.g. to
+// apply a configuration change) without stopping the supervisor. Such a restart is not a
+// failure, the restart policy doesn't apply. The supervisor also keeps the built-in metrics of
+// the application, please refer to `ApplicationMetrics`.
+//
+// An async application is supervised the same way: the supervisor thread spawns the futures of
+// the application on the runtime, and waits for them.
+pub(crate) fn supervise(
+    index: usize,
+    application: RegisteredApplication,
+    context: RunContext,
+    events: Sender<SupervisorEvent>,
+    runtime: Option<Handle>,
+    gates: StartGates,
+    replicas: ReplicaSet,
+) {
+    let replica = context.replica();
+    let identity = application.replica_identity(index, replica);
+    let critical = application.options().critical;
+    let mut tracker = RestartTracker::new(application.options().restart_policy.clone());
+    let metrics = ApplicationMetrics::new(&context);
+    let mut starting = true;
+    metrics.set_state(ApplicationState::Starting);
+    loop {
+        if starting {
+            gates.initialize.wait();
+            if context.is_exit_requested() {
+                metrics.set_state(ApplicationState::Stopped);
+                return;
+            }
+        }
+        let instance_context = context.with_shutdown_token(context.shutdown_token().child());
+        let instance = create(&identity, &application, &context).and_then(|instance| {
+            instance.initialize(&identity, &instance_context, runtime.as_ref()).map(|_| instance)
+        });
+        if starting {
+            starting = false;
+            if let Err(e) = instance {
+                metrics.set_state(ApplicationState::Failed);
+                events.send(SupervisorEvent::Initialized(index, replica, Err(e))).unwrap_or_default();
+                return;
+            }
+            events.send(SupervisorEvent::Initialized(index, replica, Ok(()))).unwrap_or_default();
+            gates.run.wait();
+            if context.is_exit_requested() {
+                metrics.set_state(ApplicationState::Stopped);
+                return;
+            }
+        }
+
+        let result = instance.and_then(|instance| {
+            metrics.set_state(ApplicationState::Running);
+            let live = LiveInstance { instance: instance.clone(), context: instance_context.clone() };
+            replicas.publish(replica, Some(live));
+            context.heartbeat_clock().start();
+            let result = instance.run(&identity, &instance_context, runtime.as_ref());
+            replicas.publish(replica, None);
+            result
+        });
+        // The instance which misses its heartbeat may be requested to exit by the watchdog, its
+        // exit is a failure.
+        let hung = context.heartbeat_clock().stop();
+        let result = match result {
+            Ok(()) if hung => Err(ServiceError::of_kind(ErrorKind::Timeout, format!("{} misses its heartbeat. ", identity))),
+            result => result,
+        };
+        // The context is cancelled only if the service is stopping, or if the host restarts the
+        // instance.
+        if instance_context.is_exit_requested() && !context.is_exit_requested() && !hung {
+            log::info!("{} exits, restart it with the new configuration.", identity);
+            metrics.set_state(ApplicationState::Restarting);
+            metrics.restarts.inc();
+            context.pause_signal().set_supported(false);
+            continue;
+        }
+        let failed = result.is_err();
+        if failed {
+            let failures = context.record_failure();
+            log::warn!("{} has failed {} time(s).", identity, failures);
+        }
+        // The application can't acknowledge a pause request until it is restarted.
+        context.pause_signal().set_supported(false);
+
+        if context.is_exit_requested() {
+            metrics.set_state(if failed { ApplicationState::Failed } else { ApplicationState::Stopped });
+            return;
+        }
+
+        // The exit code of the service tells which application fails, unless the application
+        // gives its error an exit code of its own. The error is the source of the failure, so
+        // its kind is still known.
+        let exit_code = match &result {
+            Err(e) if e.has_own_exit_code() => e.exit_code,
+            _ => exit_code::application_failure(index),
+        };
+        let mut error = result.err();
+        let mut failure = |message: String| {
+            let failure = match error.take() {
+                Some(e) => ServiceError::caused_by(e, &message),
+                None => ServiceError::new(message),
+            };
+            failure.with_kind(ErrorKind::Application).with_exit_code(exit_code)
+                .with_context("application", application.name().map(String::from).unwrap_or_else(|| { index.to_string() }))
+                .with_context("replica", replica)
+        };
+        match tracker.decide(failed) {
+            RestartDecision::Finish if failed && critical => {
+                metrics.set_state(ApplicationState::Failed);
+                log::error!("{} is critical and fails, stop the service.", identity);
+                let message = format!("{} is critical and fails. ", identity);
+                events.send(SupervisorEvent::Failed(failure(message)))
+                    .unwrap_or_default();
+                return;
+            },
+            RestartDecision::Finish => {
+                metrics.set_state(if failed { ApplicationState::Failed } else { ApplicationState::Stopped });
+                log::info!("{} exits (failed: {}).", identity, failed);
+                return;
+            },
+            RestartDecision::Restart(delay) => {
+                metrics.set_state(ApplicationState::Restarting);
+                metrics.restarts.inc();
+                log::warn!("{} exits (failed: {}), restart it in {:?}.", identity, failed, delay);
+                if !context.shutdown_token().sleep_or_cancel(delay) {
+                    metrics.set_state(ApplicationState::Stopped);
+                    return;
+                }
+            },
+            // A critical application can't be left failed.
+            RestartDecision::Escalate(Escalation::LeaveFailed) if !critical => {
+                metrics.set_state(ApplicationState::Failed);
+                log::error!("{} is restarted too often, leave it failed.", identity);
+                return;
+            },
+            RestartDecision::Escalate(_) => {
+                metrics.set_state(ApplicationState::Failed);
+                log::error!("{} is restarted too often, stop the service.", identity);
+                let message = format!("{} is restarted too often. ", identity);
+                events.send(SupervisorEvent::Failed(failure(message)))
+                    .unwrap_or_default();
+                return;
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ApplicationState {
+    Starting,
+    Running,
+    Restarting,
+    Stopped,
+    Failed,
+}
+
+impl ApplicationState {
+    const ALL: [ApplicationState; 5] = [
+        ApplicationState::Starting,
+        ApplicationState::Running,
+        ApplicationState::Restarting,
+        ApplicationState::Stopped,
+        ApplicationState::Failed,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ApplicationState::Starting => "starting",
+            ApplicationState::Running => "running",
+            ApplicationState::Restarting => "restarting",
+            ApplicationState::Stopped => "stopped",
+            ApplicationState::Failed => "failed",
+        }
+    }
+}
+
+// The built-in metrics of a replica of an application, labelled with the application and the
+// replica by the registry of the run context:
+//
+// (1) `service_application_state`: one series per state, the current state is 1, the others 0.
+// (2) `service_application_restarts_total`: how many times the application is restarted.
+// (3) `service_application_errors_total`: how many errors are delivered to `handle_error`.
+struct ApplicationMetrics {
+    states: Vec<(ApplicationState, Gauge)>,
+    restarts: Counter,
+}
+
+impl ApplicationMetrics {
+    fn new(context: &RunContext) -> ApplicationMetrics {
+        let metrics = context.metrics();
+        let states = ApplicationState::ALL.iter()
+            .map(|state| {
+                let gauge = metrics.with_label("state", state.as_str())
+                    .gauge("service_application_state", "The state of the application, 1 for the current state.");
+                (*state, gauge)
+            })
+            .collect();
+        let restarts = metrics.counter("service_application_restarts_total", "The restarts of the application.");
+        // Registered up front, so that the series is exposed before the first error.
+        errors(context);
+        ApplicationMetrics { states, restarts }
+    }
+
+    fn set_state(&self, current: ApplicationState) {
+        for (state, gauge) in self.states.iter() {
+            gauge.set(if *state == current { 1.0 } else { 0.0 });
+        }
+    }
+}
+
+fn errors(context: &RunContext) -> Counter {
+    context.metrics().counter("service_application_errors_total", "The errors handled by the application.")
+}
+
+// The gates the supervisor waits for before it starts the application. The host opens them by
+// cancelling the tokens.
+pub(crate) struct StartGates {
+    pub(crate) initialize: ShutdownToken,
+    pub(crate) run: ShutdownToken,
+}
+
+// The replicas of an application: the replicas the host keeps running (including the failed
+// ones) and the running instance of each of them, by replica index. It is shared by the
+// supervisors of the replicas and the host.
+#[derive(Clone, Default)]
+pub(crate) struct ReplicaSet {
+    shared: Arc<Mutex<Replicas>>,
+}
+
+#[derive(Default)]
+struct Replicas {
+    expected: BTreeSet<usize>,
+    instances: BTreeMap<usize, LiveInstance>,
+}
+
+impl ReplicaSet {
+    // The host expects the replica to run until it is scaled down.
+    pub(crate) fn expect(&self, replica: usize, expected: bool) {
+        let mut replicas = self.lock();
+        if expected {
+            replicas.expected.insert(replica);
+        } else {
+            replicas.expected.remove(&replica);
+        }
+    }
+
+    fn publish(&self, replica: usize, live: Option<LiveInstance>) {
+        let mut replicas = self.lock();
+        match live {
+            Some(live) => replicas.instances.insert(replica, live),
+            None => replicas.instances.remove(&replica),
+        };
+    }
+
+    // The expected replicas and their running instance, if any. The lock is not held while the
+    // caller uses the instances, a supervisor may replace its instance in the meantime.
+    pub(crate) fn snapshot(&self) -> Vec<(usize, Option<LiveInstance>)> {
+        let replicas = self.lock();
+        replicas.expected.iter().map(|replica| (*replica, replicas.instances.get(replica).cloned())).collect()
+    }
+
+    // The running instances, including the ones of the replicas being scaled down.
+    pub(crate) fn live(&self) -> Vec<(usize, LiveInstance)> {
+        self.lock().instances.iter().map(|(replica, live)| (*replica, live.clone())).collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Replicas> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LiveInstance {
+    pub(crate) instance: ApplicationInstance,
+    // The context of the instance, cancelling its token restarts the instance.
+    pub(crate) context: RunContext,
+}
+
+impl LiveInstance {
+    // Delivers a configuration change to the instance. The instance applies it live if it
+    // supports it, otherwise (or if it fails to) it is restarted, and the new instance gets the
+    // new configuration from the factory.
+    pub(crate) fn apply_configuration(&self, identity: &str, change: &ConfigurationChange) {
+        if self.instance.supports_live_reload() {
+            match self.instance.on_config_changed(identity, &self.context, change) {
+                Ok(()) => {
+                    log::info!("{} applies the new configuration.", identity);
+                    return;
+                },
+                Err(e) => log::warn!("{} fails to apply the new configuration: {}", identity, e),
+            }
+        }
+        self.context.shutdown_token().cancel();
+    }
+
+    // Delivers a user-defined command to the instance, on the command dispatcher thread.
+    pub(crate) fn handle_command(&self, identity: &str, command: &UserCommand) {
+        match self.instance.on_command(identity, &self.context, command) {
+            Ok(()) => log::info!("{} handles command '{}'.", identity, command.name),
+            Err(e) => log::warn!("{} fails to handle command '{}': {}", identity, command.name, e),
+        }
+    }
+}
+
+// A fresh instance of the application.
+#[derive(Clone)]
+pub(crate) enum ApplicationInstance {
+    Simple(Arc<dyn SimpleApplication + Send + Sync>),
+    Async(Arc<dyn AsyncApplication>),
+}
+
+// A panic in the factory can't be delivered to `handle_error`, since there is no instance yet.
+// It is handled by the restart policy like any other failure, and so is an invalid configuration.
+fn create(identity: &str, application: &RegisteredApplication, context: &RunContext) -> ServiceResult<ApplicationInstance> {
+    let configuration = context.configuration();
+    match application.factory() {
+        RegisteredFactory::Simple(factory) => panic_guard::call(identity, || { Ok(ApplicationInstance::Simple(Arc::from(factory()))) }),
+        RegisteredFactory::Async(factory) => panic_guard::call(identity, || { Ok(ApplicationInstance::Async(factory())) }),
+        RegisteredFactory::ConfiguredSimple(factory) => panic_guard::call(identity, || {
+            factory(&configuration).map(|application| { ApplicationInstance::Simple(Arc::from(application)) })
+        }),
+        RegisteredFactory::ConfiguredAsync(factory) => panic_guard::call(identity, || {
+            factory(&configuration).map(ApplicationInstance::Async)
+        }),
+    }.inspect_err(|e| { log::error!("Fail to create the application: {}", e) })
+}
+
+impl ApplicationInstance {
+    fn initialize(&self, identity: &str, context: &RunContext, runtime: Option<&Handle>) -> ServiceResult<()> {
+        match self {
+            ApplicationInstance::Simple(app) => panic_guard::call(identity, || { app.initialize(context) }),
+            ApplicationInstance::Async(app) =>
+                block_on(identity, runtime, || { app.clone().initialize(context.clone()) }),
+        }.inspect_err(|e| { self.handle_error(identity, context, e) })
+    }
+
+    fn run(&self, identity: &str, context: &RunContext, runtime: Option<&Handle>) -> ServiceResult<()> {
+        match self {
+            ApplicationInstance::Simple(app) => {
+                context.pause_signal().set_supported(app.supports_pause());
+                panic_guard::call(identity, || { app.run_with_context(context) })
+            },
+            ApplicationInstance::Async(app) => block_on(identity, runtime, || { app.clone().run(context.clone()) }),
+        }.inspect_err(|e| { self.handle_error(identity, context, e) })
+    }
+
+    fn supports_live_reload(&self) -> bool {
+        match self {
+            ApplicationInstance::Simple(app) => app.supports_live_reload(),
+            ApplicationInstance::Async(app) => app.supports_live_reload(),
+        }
+    }
+
+    fn on_config_changed(&self, identity: &str, context: &RunContext, change: &ConfigurationChange) -> ServiceResult<()> {
+        panic_guard::call(identity, || {
+            match self {
+                ApplicationInstance::Simple(app) => app.on_config_changed(change),
+                ApplicationInstance::Async(app) => app.on_config_changed(change),
+            }
+        }).inspect_err(|e| { self.handle_error(identity, context, e) })
+    }
+
+    fn on_command(&self, identity: &str, context: &RunContext, command: &UserCommand) -> ServiceResult<()> {
+        panic_guard::call(identity, || {
+            match self {
+                ApplicationInstance::Simple(app) => app.on_command(command),
+                ApplicationInstance::Async(app) => app.on_command(command),
+            }
+        }).inspect_err(|e| { self.handle_error(identity, context, e) })
+    }
+
+    // A panic in the health check makes the application unhealthy. The backtrace is left out of
+    // the detail, it is printed by the panic hook already.
+    pub(crate) fn health(&self, identity: &str) -> HealthReport {
+        panic_guard::call(identity, || {
+            Ok(match self {
+                ApplicationInstance::Simple(app) => app.health(),
+                ApplicationInstance::Async(app) => app.health(),
+            })
+        }).unwrap_or_else(|e| { HealthReport::unhealthy(e.message.lines().next().unwrap_or_default()) })
+    }
+
+    // Delivers the error (or panic) of the application to `handle_error`, which may panic as
+    // well, that must not take the supervisor down.
+    fn handle_error(&self, identity: &str, context: &RunContext, error: &ServiceError) {
+        errors(context).inc();
+        panic_guard::call(identity, || {
+            match self {
+                ApplicationInstance::Simple(app) => app.handle_error(error),
+                ApplicationInstance::Async(app) => app.handle_error(error),
+            }
+            Ok(())
+        }).unwrap_or_else(|e| {
+            log::error!("Fail to handle application error: {}", e);
+        });
+    }
+}
+
+// Spawns the future of an async application on the runtime and waits for it.
+fn block_on<F>(identity: &str, runtime: Option<&Handle>, future: F) -> ServiceResult<()>
+    where F: FnOnce() -> BoxFuture<ServiceResult<()>> {
+    let runtime = runtime.ok_or_else(|| { ServiceError::of_kind(ErrorKind::Platform, "The async runtime is not available. ") })?;
+    let future = panic_guard::call(identity, || { Ok(future()) })?;
+    let task = runtime.spawn(panic_guard::call_async(identity, future));
+    // The task is cancelled if the runtime shuts down before the application exits.
+    runtime.block_on(task)
+        .unwrap_or_else(|e| { Err(ServiceError::caused_by(e, "The async application is cancelled. ").with_kind(ErrorKind::Cancelled)) })
+}
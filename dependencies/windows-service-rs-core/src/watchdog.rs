@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::error::{ErrorKind, ServiceError};
+use crate::heartbeat::{HeartbeatPolicy, MissedHeartbeat};
+use crate::host::StatusReporter;
+use crate::registry::RegisteredApplication;
+use crate::shutdown_token::ShutdownToken;
+use crate::supervisor::{ReplicaSet, SupervisorEvent};
+
+// The watchdog checks the heartbeats at least this often.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+// The watchdog detects the applications which are hung: a deadlocked application still has a
+// live thread, but it stops sending the heartbeats it promises (please refer to
+// `ApplicationOptions::heartbeat`). When a running replica misses its heartbeat, the watchdog:
+//
+// (1) Logs it once, and counts it in `service_application_missed_heartbeats_total`. The health
+//     monitor reports the application as unhealthy meanwhile.
+// (2) Acts as the `MissedHeartbeat` of the application tells: it requests the instance to exit
+//     (and the supervisor restarts it as a failure), or it stops the service.
+//
+// The watchdog also keeps the watchdog of the service manager fed (E.g. `WATCHDOG=1` for a
+// systemd unit with `WatchdogSec`), but only while no application misses its heartbeat, so that
+// the service manager kills and restarts a hung service. It runs only if an application has a
+// heartbeat or the service manager watches the service.
+pub(crate) struct Watchdog {
+    token: ShutdownToken,
+    handle: JoinHandle<()>,
+}
+
+struct WatchedApplication {
+    index: usize,
+    application: RegisteredApplication,
+    replicas: ReplicaSet,
+    policy: Option<HeartbeatPolicy>,
+}
+
+impl Watchdog {
+    pub(crate) fn start(
+        applications: &[RegisteredApplication],
+        replicas: &[ReplicaSet],
+        reporter: Arc<dyn StatusReporter>,
+        events: Sender<SupervisorEvent>,
+    ) -> Option<Watchdog> {
+        let applications: Vec<WatchedApplication> = applications.iter().zip(replicas).enumerate()
+            .map(|(index, (application, replicas))| WatchedApplication {
+                index,
+                application: application.clone(),
+                replicas: replicas.clone(),
+                policy: application.options().heartbeat,
+            })
+            .collect();
+        let keep_alive = reporter.watchdog_interval();
+        if keep_alive.is_none() && applications.iter().all(|watched| watched.policy.is_none()) {
+            return None;
+        }
+        // Often enough to feed the service manager twice per interval, and to tell a missed
+        // heartbeat within a quarter of its timeout.
+        let check_interval = applications.iter()
+            .filter_map(|watched| watched.policy.map(|policy| policy.timeout / 4))
+            .chain(keep_alive.map(|interval| interval / 2))
+            .fold(MAX_CHECK_INTERVAL, Duration::min)
+            .max(MIN_CHECK_INTERVAL);
+        if let Some(interval) = keep_alive {
+            log::info!("The service manager expects a keep-alive every {:?}.", interval);
+        }
+
+        let token = ShutdownToken::new();
+        let thread_token = token.clone();
+        let handle = thread::spawn(move || {
+            loop {
+                // Every application is checked, even once one misses its heartbeat.
+                let alive: Vec<bool> = applications.iter().map(|watched| watched.check(&events)).collect();
+                let alive = alive.into_iter().all(|alive| alive);
+                if alive && keep_alive.is_some() {
+                    reporter.keep_alive().unwrap_or_else(|e| {
+                        log::warn!("Fail to send a keep-alive to the service manager: {}", e);
+                    });
+                }
+                if !thread_token.sleep_or_cancel(check_interval) {
+                    break;
+                }
+            }
+        });
+        Some(Watchdog { token, handle })
+    }
+
+    pub(crate) fn stop(self) {
+        self.token.cancel();
+        self.handle.join().unwrap_or_else(|e| {
+            log::error!("Watchdog error: {:?}", e);
+        });
+    }
+}
+
+impl WatchedApplication {
+    // Returns whether all the running replicas send their heartbeat.
+    fn check(&self, events: &Sender<SupervisorEvent>) -> bool {
+        let policy = match self.policy {
+            Some(policy) => policy,
+            None => return true,
+        };
+        let mut alive = true;
+        for (replica, live) in self.replicas.live() {
+            let heartbeat = live.context.heartbeat_clock();
+            // A paused application is not expected to send heartbeats.
+            if live.context.pause_signal().is_paused() {
+                heartbeat.beat();
+            }
+            let identity = self.application.replica_identity(self.index, replica);
+            let overdue = heartbeat.overdue(policy.timeout);
+            alive = alive && overdue.is_none();
+            if !heartbeat.set_missed(overdue.is_some()) {
+                continue;
+            }
+            let elapsed = match overdue {
+                Some(elapsed) => elapsed,
+                None => {
+                    log::info!("{} sends its heartbeat again.", identity);
+                    continue;
+                },
+            };
+
+            log::error!("{} has not sent a heartbeat for {:?}, it may be hung.", identity, elapsed);
+            live.context.metrics()
+                .counter("service_application_missed_heartbeats_total", "How many times the application misses its heartbeat.")
+                .inc();
+            match policy.on_missed {
+                MissedHeartbeat::Report => {},
+                MissedHeartbeat::Restart => {
+                    log::warn!("{} misses its heartbeat, request it to exit and restart it.", identity);
+                    heartbeat.set_hung();
+                    live.context.shutdown_token().cancel();
+                    live.context.pause_signal().request_continue();
+                },
+                MissedHeartbeat::StopService => {
+                    log::error!("{} misses its heartbeat, stop the service.", identity);
+                    let label = self.application.name().map(String::from).unwrap_or_else(|| { self.index.to_string() });
+                    let message = format!("{} has not sent a heartbeat for {:?}. ", identity, elapsed);
+                    events.send(SupervisorEvent::Failed(ServiceError::of_kind(ErrorKind::Timeout, message)
+                        .with_context("application", label)
+                        .with_context("replica", replica)))
+                        .unwrap_or_default();
+                },
+            }
+        }
+        alive
+    }
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use windows_service_rs_core::application::SimpleApplication;
+use windows_service_rs_core::error::{ErrorKind, ServiceError, ServiceResult};
+use windows_service_rs_core::exit_code;
+use windows_service_rs_core::host::HostState;
+use windows_service_rs_core::registry::ApplicationOptions;
+use windows_service_rs_core::run_context::RunContext;
+use windows_service_rs_core::service_builder::ServiceBuilder;
+use common::RecordingBackend;
+
+struct IdleApplication {}
+
+impl SimpleApplication for IdleApplication {
+    fn handle_error(&self, _error: &ServiceError) {}
+}
+
+// Fails as soon as it runs, with an I/O error which may set its own exit code.
+struct FailingApplication {
+    exit_code: Option<u32>,
+}
+
+impl SimpleApplication for FailingApplication {
+    fn handle_error(&self, _error: &ServiceError) {}
+
+    fn run_with_context(&self, _context: &RunContext) -> ServiceResult<()> {
+        let error = ServiceError::of_kind(ErrorKind::Io, "The disk is full. ");
+        Err(match self.exit_code {
+            Some(exit_code) => error.with_exit_code(exit_code),
+            None => error,
+        })
+    }
+}
+
+// Runs an idle application and a failing critical one, returns the error of the service and
+// the exit code of its last report.
+fn run_with_failure(exit_code: Option<u32>) -> (ServiceError, u32) {
+    let reports = Arc::new(Mutex::new(vec![]));
+    let (backend, _controls) = RecordingBackend::new(reports.clone());
+
+    let error = ServiceBuilder::new()
+        .service_name("application-failure-test")
+        .backend(Box::new(backend))
+        .application(|| { Box::new(IdleApplication {}) })
+        .application_with_options(ApplicationOptions::new().name("failing").critical(true), move || {
+            Box::new(FailingApplication { exit_code })
+        })
+        .run()
+        .unwrap_err();
+
+    let last = *reports.lock().unwrap().last().unwrap();
+    assert_eq!(last.state, HostState::Stopped);
+    (error, last.exit_code)
+}
+
+// The exit code tells which application fails, whatever the kind of its error.
+#[test]
+fn stops_with_the_exit_code_of_the_application() {
+    let (error, reported) = run_with_failure(None);
+
+    assert_eq!(error.kind(), ErrorKind::Application);
+    assert_eq!(error.exit_code, exit_code::application_failure(1));
+    assert_eq!(reported, exit_code::application_failure(1));
+}
+
+#[test]
+fn keeps_the_exit_code_the_error_sets_itself() {
+    let (error, reported) = run_with_failure(Some(42));
+
+    assert_eq!(error.exit_code, 42);
+    assert_eq!(reported, 42);
+}
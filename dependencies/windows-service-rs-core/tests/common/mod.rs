@@ -0,0 +1,47 @@
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use windows_service_rs_core::error::ServiceResult;
+use windows_service_rs_core::host::{HostBackend, HostControl, HostSession, HostStatus, ServiceMain, StatusReporter};
+use windows_service_rs_core::service_config::ServiceConfig;
+
+pub type Reports = Arc<Mutex<Vec<HostStatus>>>;
+
+// A backend which records the status reports, like a service manager would. The controls sent
+// through the sender returned by `new` are delivered to the service.
+pub struct RecordingBackend {
+    reports: Reports,
+    controls: Mutex<Option<Receiver<HostControl>>>,
+}
+
+impl RecordingBackend {
+    pub fn new(reports: Reports) -> (RecordingBackend, Sender<HostControl>) {
+        let (control_sender, control_receiver) = mpsc::channel();
+        (RecordingBackend { reports, controls: Mutex::new(Some(control_receiver)) }, control_sender)
+    }
+}
+
+struct RecordingReporter {
+    reports: Reports,
+}
+
+impl StatusReporter for RecordingReporter {
+    fn report(&self, status: HostStatus) -> ServiceResult<()> {
+        self.reports.lock().unwrap().push(status);
+        Ok(())
+    }
+}
+
+impl HostBackend for RecordingBackend {
+    fn name(&self) -> &str {
+        "recording"
+    }
+
+    fn run(&self, _config: &ServiceConfig, service_main: ServiceMain) -> ServiceResult<()> {
+        let controls = self.controls.lock().unwrap().take().expect("The backend runs once.");
+        service_main(HostSession {
+            arguments: vec![],
+            reporter: Arc::new(RecordingReporter { reports: self.reports.clone() }),
+            controls,
+        })
+    }
+}
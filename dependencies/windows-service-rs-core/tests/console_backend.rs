@@ -0,0 +1,79 @@
+#![cfg(unix)]
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use signal_hook::consts::{SIGCONT, SIGINT, SIGTSTP};
+use signal_hook::low_level::raise;
+use windows_service_rs_core::application::SimpleApplication;
+use windows_service_rs_core::error::{ServiceError, ServiceResult};
+use windows_service_rs_core::host::console::ConsoleBackend;
+use windows_service_rs_core::run_context::RunContext;
+use windows_service_rs_core::service_builder::ServiceBuilder;
+use windows_service_rs_core::service_config::AcceptedControls;
+
+// What the application goes through, in order.
+type Events = Arc<Mutex<Vec<&'static str>>>;
+
+struct RecordingApplication {
+    events: Events,
+}
+
+impl SimpleApplication for RecordingApplication {
+    fn handle_error(&self, _error: &ServiceError) {}
+
+    fn run_with_context(&self, context: &RunContext) -> ServiceResult<()> {
+        record(&self.events, "running");
+        while !context.is_exit_requested() {
+            if context.pause_signal().is_pause_requested() {
+                record(&self.events, "paused");
+                context.pause_signal().wait_if_paused();
+                record(&self.events, "resumed");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        record(&self.events, "stopped");
+        Ok(())
+    }
+
+    fn supports_pause(&self) -> bool { true }
+}
+
+fn record(events: &Events, event: &'static str) {
+    events.lock().unwrap().push(event);
+}
+
+fn wait_for(events: &Events, event: &'static str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !events.lock().unwrap().contains(&event) {
+        assert!(Instant::now() < deadline, "The application is not {} in time.", event);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Ctrl+Z, SIGCONT and Ctrl+C pause, continue and stop the service run in the foreground.
+#[test]
+fn pauses_continues_and_stops() {
+    let events: Events = Arc::default();
+    let events_for_factory = events.clone();
+    let events_for_signals = events.clone();
+    let signals = thread::spawn(move || {
+        wait_for(&events_for_signals, "running");
+        raise(SIGTSTP).unwrap();
+        wait_for(&events_for_signals, "paused");
+        raise(SIGCONT).unwrap();
+        wait_for(&events_for_signals, "resumed");
+        raise(SIGINT).unwrap();
+    });
+
+    ServiceBuilder::new()
+        .service_name("console-backend-test")
+        .accepted_controls(AcceptedControls::STOP | AcceptedControls::PAUSE_CONTINUE)
+        .backend(Box::new(ConsoleBackend::new()))
+        .application(move || { Box::new(RecordingApplication { events: events_for_factory.clone() }) })
+        .run()
+        .unwrap();
+    signals.join().unwrap();
+
+    assert_eq!(*events.lock().unwrap(), vec!["running", "paused", "resumed", "stopped"]);
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::{Arc, Mutex};
+use windows_service_rs_core::application::SimpleApplication;
+use windows_service_rs_core::error::{ErrorKind, ServiceError};
+use windows_service_rs_core::exit_code;
+use windows_service_rs_core::host::HostState;
+use windows_service_rs_core::service_builder::ServiceBuilder;
+use common::RecordingBackend;
+
+struct IdleApplication {}
+
+impl SimpleApplication for IdleApplication {
+    fn handle_error(&self, _error: &ServiceError) {}
+}
+
+// The service manager is told that the service has stopped, rather than waiting for it to start.
+#[test]
+fn reports_stopped_when_the_health_port_is_taken() {
+    let taken = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let reports = Arc::new(Mutex::new(vec![]));
+    let (backend, _controls) = RecordingBackend::new(reports.clone());
+
+    let error = ServiceBuilder::new()
+        .service_name("failed-start-test")
+        .health_port(taken.local_addr().unwrap().port())
+        .backend(Box::new(backend))
+        .application(|| { Box::new(IdleApplication {}) })
+        .run()
+        .unwrap_err();
+
+    assert_eq!(error.kind(), ErrorKind::Io);
+    let last = *reports.lock().unwrap().last().unwrap();
+    assert_eq!(last.state, HostState::Stopped);
+    assert_eq!(last.exit_code, exit_code::IO_ERROR);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+use windows_service_rs_core::application::SimpleApplication;
+use windows_service_rs_core::error::{ServiceError, ServiceResult};
+use windows_service_rs_core::host::HostControl;
+use windows_service_rs_core::registry::ApplicationOptions;
+use windows_service_rs_core::run_context::RunContext;
+use windows_service_rs_core::service_builder::ServiceBuilder;
+use windows_service_rs_core::service_config::AcceptedControls;
+use common::RecordingBackend;
+
+const SCALE_UP: u8 = 140;
+const SCALE_DOWN: u8 = 141;
+
+// The replicas running right now, by replica index.
+type Live = Arc<Mutex<BTreeSet<usize>>>;
+
+struct ConsumerApplication {
+    live: Live,
+}
+
+impl SimpleApplication for ConsumerApplication {
+    fn handle_error(&self, _error: &ServiceError) {}
+
+    fn run_with_context(&self, context: &RunContext) -> ServiceResult<()> {
+        self.live.lock().unwrap().insert(context.replica());
+        while !context.is_exit_requested() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.live.lock().unwrap().remove(&context.replica());
+        Ok(())
+    }
+}
+
+fn wait_for(live: &Live, expected: &[usize]) {
+    let expected: BTreeSet<usize> = expected.iter().copied().collect();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while *live.lock().unwrap() != expected {
+        assert!(Instant::now() < deadline, "The replicas are {:?} rather than {:?}.", live.lock().unwrap(), expected);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// The replica count is checked again after the controls in flight are handled.
+fn stays(live: &Live, expected: &[usize]) {
+    thread::sleep(Duration::from_millis(500));
+    wait_for(live, expected);
+}
+
+fn write_replicas(config_file: &Path, replicas: usize) {
+    fs::write(config_file, format!("[replicas]\nconsumer = {}\n", replicas)).unwrap();
+}
+
+fn send(controls: &Sender<HostControl>, control: HostControl) {
+    controls.send(control).unwrap();
+}
+
+#[test]
+fn scales_the_replicas_at_runtime() {
+    let live: Live = Arc::default();
+    let config_file = env::temp_dir().join(format!("scaling-test-{}.toml", std::process::id()));
+    write_replicas(&config_file, 2);
+    let (backend, controls) = RecordingBackend::new(Arc::default());
+
+    let factory_live = live.clone();
+    let service_config_file = config_file.clone();
+    let service = thread::spawn(move || {
+        ServiceBuilder::new()
+            .service_name("scaling-test")
+            .accepted_controls(AcceptedControls::STOP | AcceptedControls::PARAM_CHANGE)
+            .config_file(service_config_file)
+            .backend(Box::new(backend))
+            .application_with_options(ApplicationOptions::new().name("consumer").scale_commands(SCALE_UP, SCALE_DOWN), move || {
+                Box::new(ConsumerApplication { live: factory_live.clone() })
+            })
+            .run()
+    });
+    wait_for(&live, &[0, 1]);
+
+    send(&controls, HostControl::User(SCALE_UP));
+    wait_for(&live, &[0, 1, 2]);
+
+    // The last replicas exit first.
+    send(&controls, HostControl::User(SCALE_DOWN));
+    wait_for(&live, &[0, 1]);
+    send(&controls, HostControl::User(SCALE_DOWN));
+    wait_for(&live, &[0]);
+
+    // The single replica is not scaled down.
+    send(&controls, HostControl::User(SCALE_DOWN));
+    stays(&live, &[0]);
+
+    // Neither is a reloaded configuration without replicas taking effect.
+    write_replicas(&config_file, 0);
+    send(&controls, HostControl::ParamChange);
+    stays(&live, &[0]);
+    write_replicas(&config_file, 3);
+    send(&controls, HostControl::ParamChange);
+    wait_for(&live, &[0, 1, 2]);
+
+    send(&controls, HostControl::Stop);
+    service.join().unwrap().unwrap();
+    fs::remove_file(&config_file).unwrap_or_default();
+    assert!(live.lock().unwrap().is_empty());
+}
@@ -8,7 +8,7 @@ param (
 ./build.ps1 -Release:$Release
 
 if ($Release -eq $true) {
-    cargo run --release --bin service-installer -- create --bin ".\target\release\sample-rust-service.exe" --name "sample-rust-service" --disp "Sample Rust Service" --desc "This is a sample service created by RUST programming langauge" --auto
+    cargo run --release --bin service-installer -- create --bin ".\target\release\sample-rust-service.exe" --name "sample-rust-service" --disp "Sample Rust Service" --desc "This is a sample service created by RUST programming langauge" --auto --restart-on-failure --config ".\crates\sample-rust-service\config.toml"
 } else {
-    cargo run --bin service-installer -- create --bin ".\target\debug\sample-rust-service.exe" --name "sample-rust-service" --disp "Sample Rust Service" --desc "This is a sample service created by RUST programming langauge" --auto
+    cargo run --bin service-installer -- create --bin ".\target\debug\sample-rust-service.exe" --name "sample-rust-service" --disp "Sample Rust Service" --desc "This is a sample service created by RUST programming langauge" --auto --restart-on-failure --config ".\crates\sample-rust-service\config.toml"
 }
@@ -4,5 +4,8 @@ pub struct Argument {
     pub service_name: String,
     pub display_name: String,
     pub description: String,
-    pub auto_start: bool
+    pub auto_start: bool,
+    pub restart_on_failure: bool,
+    pub config_path: Option<String>,
+    pub control_code: Option<u32>
 }
\ No newline at end of file
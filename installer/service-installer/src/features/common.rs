@@ -47,6 +47,13 @@ pub fn try_stop_service(context:&WindowsServiceOperatingContext) -> Result<(), I
     Ok(())
 }
 
+pub fn try_send_control(context:&WindowsServiceOperatingContext, control_code:u32) -> InstallerResult<()> {
+    print!("Sending control code {} to service {}...", control_code.to_string().as_str().cyan(), context.service_name.as_str().cyan());
+    context.send_control(control_code)?;
+    print_done();
+    Ok(())
+}
+
 pub fn try_start_service(context:&WindowsServiceOperatingContext) -> InstallerResult<()> {
     print!("Trying to start service {}...", context.service_name.as_str().cyan());
     context.start_service()?;
@@ -0,0 +1,73 @@
+use crate::features::features::Feature;
+use clap::{App, ArgMatches, SubCommand, Arg};
+use crate::error::{InstallerResult, InstallerError};
+use crate::arguments::Argument;
+use colored::Colorize;
+use crate::features::service_wrapper::WindowsServiceOperatingContext;
+use windows_service::service_manager::ServiceManagerAccess;
+use windows_service::service::ServiceAccess;
+use crate::features::common;
+
+// Sends a user-defined control code to a running service, E.g.
+// `service-installer control --name sample-rust-service --code 130`. The service dispatches it
+// to the application which registers the code.
+pub struct ControlServiceFeature {}
+
+const COMMAND_NAME:&str = "control";
+const SERVICE_NAME_KEY:&str = "service name";
+const CONTROL_CODE_KEY:&str = "control code";
+
+// Windows reserves the control codes below 128.
+const USER_CONTROL_MIN:u32 = 128;
+const USER_CONTROL_MAX:u32 = 255;
+
+impl Feature for ControlServiceFeature {
+    fn create_argument_parser(&self) -> App<'_, '_> {
+        SubCommand::with_name(COMMAND_NAME)
+            .arg(
+                Arg::with_name(SERVICE_NAME_KEY)
+                    .long("name")
+                    .required(true)
+                    .multiple(false)
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name(CONTROL_CODE_KEY)
+                    .long("code")
+                    .required(true)
+                    .multiple(false)
+                    .takes_value(true)
+                    .help("The user-defined control code, 128 to 255.")
+            )
+    }
+
+    fn create_argument_from_matches(&self, sub_command_matches: &ArgMatches) -> InstallerResult<Option<Argument>> {
+        let control_code = sub_command_matches.value_of(CONTROL_CODE_KEY)
+            .and_then(|code| { code.trim().parse::<u32>().ok() })
+            .filter(|code| { (USER_CONTROL_MIN..=USER_CONTROL_MAX).contains(code) })
+            .ok_or(InstallerError::new(format!("Invalid control code, it must be {} to {}.", USER_CONTROL_MIN, USER_CONTROL_MAX)))?;
+        InstallerResult::Ok(Option::Some(Argument {
+            action_type: String::from(COMMAND_NAME),
+            executable_path: String::default(),
+            service_name: String::from(sub_command_matches.value_of(SERVICE_NAME_KEY).ok_or(InstallerError::new("Invalid service name."))?),
+            display_name: String::default(),
+            description: String::default(),
+            auto_start: false,
+            restart_on_failure: false,
+            config_path: None,
+            control_code: Some(control_code)
+        }))
+    }
+
+    fn execute_service_feature(&self, argument: &Argument) -> InstallerResult<()> {
+        let control_code = argument.control_code.ok_or(InstallerError::new("Invalid control code."))?;
+        println!("Attempt to send control code {} to service {}", control_code.to_string().as_str().cyan(), argument.service_name.as_str().cyan());
+
+        let context = WindowsServiceOperatingContext::from(
+            &argument.service_name, ServiceManagerAccess::CONNECT, ServiceAccess::USER_DEFINED_CONTROL)?;
+
+        common::try_send_control(&context, control_code)
+    }
+
+    fn get_sub_command_name(&self) -> String { String::from(COMMAND_NAME) }
+}
@@ -6,6 +6,7 @@ use crate::features::uninstall_service::UninstallServiceFeature;
 use crate::features::query_service::QueryServiceFeature;
 use crate::features::start_service::StartServiceFeature;
 use crate::features::stop_service::StopServiceFeature;
+use crate::features::control_service::ControlServiceFeature;
 
 pub trait Feature {
     fn create_argument_parser(&self) -> clap::App;
@@ -26,7 +27,8 @@ impl FeatureFactory {
                 Box::new(UninstallServiceFeature{}),
                 Box::new(QueryServiceFeature{}),
                 Box::new(StartServiceFeature{}),
-                Box::new(StopServiceFeature)
+                Box::new(StopServiceFeature),
+                Box::new(ControlServiceFeature{})
             ]
         }
     }
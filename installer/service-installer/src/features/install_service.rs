@@ -15,6 +15,8 @@ const DISPLAY_NAME_KEY:&str = "display name";
 const DESCRIPTION_KEY:&str = "description";
 const AUTO_START_SWITCH_KEY:&str = "auto start";
 const SERVICE_PATH_KEY:&str = "service executable path";
+const RESTART_ON_FAILURE_SWITCH_KEY:&str = "restart on failure";
+const CONFIG_PATH_KEY:&str = "configuration file path";
 
 impl Feature for InstallServiceFeature {
     fn create_argument_parser(&self) -> App {
@@ -48,6 +50,13 @@ impl Feature for InstallServiceFeature {
                     .multiple(false)
                     .takes_value(false)
             )
+            .arg(
+                Arg::with_name(RESTART_ON_FAILURE_SWITCH_KEY)
+                    .long("restart-on-failure")
+                    .required(false)
+                    .multiple(false)
+                    .takes_value(false)
+            )
             .arg(
                 Arg::with_name(SERVICE_PATH_KEY)
                     .long("bin")
@@ -55,6 +64,13 @@ impl Feature for InstallServiceFeature {
                     .multiple(false)
                     .takes_value(true)
             )
+            .arg(
+                Arg::with_name(CONFIG_PATH_KEY)
+                    .long("config")
+                    .required(false)
+                    .multiple(false)
+                    .takes_value(true)
+            )
     }
     fn create_argument_from_matches(&self, sub_command_matches: &ArgMatches) -> InstallerResult<Option<Argument>> {
         return InstallerResult::Ok(Option::Some(Argument {
@@ -63,7 +79,10 @@ impl Feature for InstallServiceFeature {
             service_name: String::from(sub_command_matches.value_of(SERVICE_NAME_KEY).ok_or(InstallerError::new("Invalid service name."))?),
             display_name: String::from(sub_command_matches.value_of(DISPLAY_NAME_KEY).ok_or(InstallerError::new("Invalid display name."))?),
             description: String::from(sub_command_matches.value_of(DESCRIPTION_KEY).ok_or(InstallerError::new("Invalid description."))?),
-            auto_start: sub_command_matches.is_present(AUTO_START_SWITCH_KEY)
+            auto_start: sub_command_matches.is_present(AUTO_START_SWITCH_KEY),
+            restart_on_failure: sub_command_matches.is_present(RESTART_ON_FAILURE_SWITCH_KEY),
+            config_path: sub_command_matches.value_of(CONFIG_PATH_KEY).map(String::from),
+            control_code: None
         }));
     }
     fn execute_service_feature(&self, argument:&Argument) -> InstallerResult<()> {
@@ -72,13 +91,20 @@ impl Feature for InstallServiceFeature {
         let service_path = get_service_path(&argument)?;
         println!("  Service executable path: {}", service_path.as_str().cyan());
 
+        let config_path = argument.config_path.as_deref().map(get_absolute_path).transpose()?;
+        if let Some(config_path) = &config_path {
+            println!("  Configuration file path: {}", config_path.as_str().cyan());
+        }
+
         print!("Creating windows service ...");
         WindowsServiceOperatingContext::create_windows_service(
             &argument.service_name,
             &argument.display_name,
             &argument.description,
             argument.auto_start,
-            &service_path)?;
+            argument.restart_on_failure,
+            &service_path,
+            config_path.as_deref())?;
         println!("{}", "Done".green());
         Ok(())
     }
@@ -89,12 +115,17 @@ impl Feature for InstallServiceFeature {
 }
 
 fn get_service_path(argument: &Argument) -> Result<String, InstallerError> {
-    let absolute_path_result = std::path::Path::new(&argument.executable_path).absolutize();
+    get_absolute_path(&argument.executable_path)
+}
+
+// The service doesn't run in the current directory, so the paths are made absolute.
+fn get_absolute_path(path: &str) -> Result<String, InstallerError> {
+    let absolute_path_result = std::path::Path::new(path).absolutize();
     let absolute_path = absolute_path_result.map_err(|e| { InstallerError::with(e,"Fail to get absolute path. ") })?;
     if absolute_path.exists() {
         return Result::Ok(String::from(absolute_path.to_str().unwrap()));
     }
 
-    let error_message = format!("The path {} does not exist.", &argument.executable_path);
+    let error_message = format!("The path {} does not exist.", path);
     return Result::Err(InstallerError::new(error_message));
 }
\ No newline at end of file
@@ -5,4 +5,5 @@ pub mod uninstall_service;
 pub mod query_service;
 pub mod start_service;
 pub mod stop_service;
+pub mod control_service;
 mod service_wrapper;
@@ -27,7 +27,10 @@ impl Feature for QueryServiceFeature {
             service_name: String::from(sub_command_matches.value_of(SERVICE_NAME_KEY).ok_or(InstallerError::new("Invalid service name."))?),
             display_name: String::default(),
             description: String::default(),
-            auto_start: false
+            auto_start: false,
+            restart_on_failure: false,
+            config_path: None,
+            control_code: None
         }))
     }
 
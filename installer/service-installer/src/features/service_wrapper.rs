@@ -5,7 +5,7 @@ use core::time;
 use crate::error::{InstallerError, InstallerResult};
 use std::thread;
 use std::ffi::{OsString};
-use windows_service::service::{Service, ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType};
+use windows_service::service::{Service, ServiceAccess, ServiceAction, ServiceActionType, ServiceErrorControl, ServiceFailureActions, ServiceFailureResetPeriod, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType, UserEventCode};
 use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
 
 pub struct WindowsServiceOperatingContext {
@@ -41,7 +41,9 @@ impl WindowsServiceOperatingContext {
         display_name:&str,
         description:&str,
         auto_start:bool,
-        service_binary_path:&str) -> Result<(), InstallerError> {
+        restart_on_failure:bool,
+        service_binary_path:&str,
+        config_path:Option<&str>) -> Result<(), InstallerError> {
         let service_manager = WindowsServiceOperatingContext::open_service_manager(
             ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
         let service_info = ServiceInfo {
@@ -51,26 +53,56 @@ impl WindowsServiceOperatingContext {
             start_type: if auto_start { ServiceStartType::AutoStart } else { ServiceStartType::OnDemand },
             error_control: ServiceErrorControl::Normal,
             executable_path: std::path::PathBuf::from(service_binary_path),
-            launch_arguments: vec![],
+            // The service reads its configuration file from the `--config` start argument.
+            launch_arguments: config_path
+                .map(|config_path| { vec![OsString::from("--config"), OsString::from(config_path)] })
+                .unwrap_or_default(),
             dependencies: vec![],
             account_name: None, // run as System
             account_password: None,
         };
 
-        let service = service_manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG)
+        // The restart action requires the START access right.
+        let service = service_manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)
             .or_else(|e| { Result::Err(InstallerError::with(e, "Fail to create Windows Service. ")) })?;
         service.set_description(description).or_else(|e| {
             Result::Err(InstallerError::with(e, "Fail to set service description. "))
         })?;
+        if restart_on_failure {
+            WindowsServiceOperatingContext::set_restart_on_failure(&service)?;
+        }
         Ok(())
     }
 
+    // Restarts the service if it crashes, or if it stops with a non-zero exit code (E.g. a
+    // critical application fails). The failure count is reset after a day without failure.
+    fn set_restart_on_failure(service:&Service) -> Result<(), InstallerError> {
+        let restart_action = || { ServiceAction { action_type: ServiceActionType::Restart, delay: time::Duration::from_secs(60) } };
+        service.update_failure_actions(ServiceFailureActions {
+            reset_period: ServiceFailureResetPeriod::After(time::Duration::from_secs(24 * 60 * 60)),
+            reboot_msg: None,
+            command: None,
+            actions: Some(vec![restart_action(), restart_action(), restart_action()]),
+        }).or_else(|e| { Result::Err(InstallerError::with(e, "Fail to set service failure actions. ")) })?;
+        service.set_failure_actions_on_non_crash_failures(true).or_else(|e| {
+            Result::Err(InstallerError::with(e, "Fail to enable failure actions on non-crash failures. "))
+        })
+    }
+
     pub fn stop_service(&self) -> Result<(), InstallerError> {
         self.service.stop()
             .and_then(|_| { Result::Ok(())})
             .or_else(|e| { Result::Err(InstallerError::with(e, "Stop service failed. ")) })
     }
 
+    pub fn send_control(&self, control_code:u32) -> InstallerResult<()> {
+        let code = UserEventCode::from_raw(control_code)
+            .map_err(|e| { InstallerError::with(e, "Invalid control code. ") })?;
+        self.service.notify(code)
+            .map(|_| ())
+            .map_err(|e| { InstallerError::with(e, "Send control code failed. ") })
+    }
+
     pub fn start_service(&self) -> InstallerResult<()> {
         let empty_arguments:[String; 0] = [];
         self.service.start(&empty_arguments)
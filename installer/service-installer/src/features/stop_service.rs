@@ -33,6 +33,9 @@ impl Feature for StopServiceFeature {
             description: String::default(),
             display_name: String::default(),
             auto_start: false,
+            restart_on_failure: false,
+            config_path: None,
+            control_code: None
         }))
     }
 
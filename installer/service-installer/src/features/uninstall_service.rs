@@ -32,7 +32,10 @@ impl Feature for UninstallServiceFeature {
             service_name: String::from(sub_command_matches.value_of(SERVICE_NAME_KEY).ok_or(InstallerError::new("Invalid service name."))?),
             display_name: String::default(),
             description: String::default(),
-            auto_start: false
+            auto_start: false,
+            restart_on_failure: false,
+            config_path: None,
+            control_code: None
         }))
     }
 
@@ -59,8 +62,12 @@ impl Feature for UninstallServiceFeature {
                 try_wait_for_status(&context, ServiceState::Running)?;
                 try_stop_and_wait(&context)?;
             },
-            ServiceState::PausePending => { try_wait_for_status(&context, ServiceState::Paused)?; },
-            ServiceState::Paused => { /* we are not sure how to do that */ },
+            ServiceState::PausePending => {
+                try_wait_for_status(&context, ServiceState::Paused)?;
+                try_stop_and_wait(&context)?;
+            },
+            // A paused service still accepts the stop control.
+            ServiceState::Paused => { try_stop_and_wait(&context)?; },
         }
 
         try_uninstall_service(context)
@@ -1,14 +1,31 @@
+#[cfg(windows)]
 use colored::Colorize;
 
+#[cfg(windows)]
 use crate::error::{InstallerError, InstallerResult};
+#[cfg(windows)]
 use crate::features::features::FeatureFactory;
+#[cfg(windows)]
 use crate::arguments::Argument;
+#[cfg(windows)]
 use clap::{AppSettings, App, ArgMatches};
 
+#[cfg(windows)]
 mod arguments;
+#[cfg(windows)]
 mod error;
+#[cfg(windows)]
 mod features;
 
+// The installer talks to the Windows service control manager. On other platforms, the service
+// is managed by the service manager of the platform (E.g. systemctl).
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("The service installer is only available on Windows.");
+    std::process::exit(1);
+}
+
+#[cfg(windows)]
 fn main() -> Result<(), InstallerError> {
     let factory = FeatureFactory::new();
 
@@ -19,6 +36,7 @@ fn main() -> Result<(), InstallerError> {
     execute_feature(&factory, &argument)
 }
 
+#[cfg(windows)]
 fn execute_feature(feature_factory:&FeatureFactory, argument:&Argument) -> InstallerResult<()> {
     let features = feature_factory.get_features();
     for feature in features {
@@ -30,6 +48,7 @@ fn execute_feature(feature_factory:&FeatureFactory, argument:&Argument) -> Insta
     InstallerResult::Ok(())
 }
 
+#[cfg(windows)]
 fn match_arguments(feature_factory:&FeatureFactory) -> Result<Argument, error::InstallerError> {
     let mut app = App::new("Windows Service Installer").setting(AppSettings::SubcommandRequired);
 